@@ -2,6 +2,7 @@
 use my_math::vec::*;
 
 use std::mem::MaybeUninit;
+use std::collections::BTreeSet;
 use std::time::Instant;
 use crate::utils;
 
@@ -19,6 +20,16 @@ pub struct Chunk {
     pub brickmap_grid_ssbo: u32,
     pub brickmap_data_ssbo: u32,
     pub pos: IVec3,
+    /// The brickmap was edited and the ssbos are out of date
+    pub dirty: bool,
+}
+
+impl Chunk {
+    /// Uploads the edits made to the brickmap
+    pub unsafe fn update_ssbos(&mut self) {
+        self.brickmap.update_ssbos(self.brickmap_grid_ssbo, &mut self.brickmap_data_ssbo);
+        self.dirty = false;
+    }
 }
 
 #[repr(C)]
@@ -43,6 +54,24 @@ impl BrickGrid {
                     z;
         return &mut self.arr[index];
     }
+    pub fn contains(&self,pos: IVec3) -> bool {
+        pos.x >= 0 && pos.x < self.size.x &&
+        pos.y >= 0 && pos.y < self.size.y &&
+        pos.z >= 0 && pos.z < self.size.z
+    }
+    /// Returns `u32::MAX` (no brick) for positions outside of the grid
+    pub fn get(&self,pos: IVec3) -> u32 {
+        if !self.contains(pos) {
+            return u32::MAX;
+        }
+        self.arr[self.index(pos)]
+    }
+    /// Index into `arr`, assuming the position is inside of the grid
+    pub fn index(&self,pos: IVec3) -> usize {
+        pos.x as usize * self.size.y as usize * self.size.z as usize + 
+        pos.y as usize * self.size.z as usize + 
+        pos.z as usize
+    }
     pub fn as_ptr(&self) -> *const u32 {
         self.arr.as_ptr()
     }
@@ -54,6 +83,16 @@ impl BrickGrid {
 pub struct BrickMap {
     pub grid: BrickGrid,
     pub data: Vec<Brick>,
+
+    // Changes since the last upload, only tracked once the ssbos exist
+    pub uploaded: bool,
+    /// Indices into `grid.arr`
+    pub dirty_cells: BTreeSet<usize>,
+    /// Indices of already uploaded bricks, everything from `uploaded_bricks` on was appended
+    pub dirty_bricks: BTreeSet<usize>,
+    pub uploaded_bricks: usize,
+    /// Number of bricks the data ssbo has room for
+    pub data_ssbo_capacity: usize,
 }
 impl BrickMap {
     pub fn new(size: IVec3) -> Self {
@@ -63,12 +102,26 @@ impl BrickMap {
         Self {
             grid: BrickGrid::new(size/8),
             data: Vec::new(),
+
+            uploaded: false,
+            dirty_cells: BTreeSet::new(),
+            dirty_bricks: BTreeSet::new(),
+            uploaded_bricks: 0,
+            data_ssbo_capacity: 0,
         }
     }
+    /// Whether the voxel position is covered by the grid
+    pub fn contains(&self, pos: IVec3) -> bool {
+        self.grid.contains(pos.div_floor(8))
+    }
+    /// Does nothing for positions outside of the grid
     pub fn add_voxel(&mut self, pos: IVec3, voxel: Voxel) {
         let grid_coords :IVec3 = pos.div_floor(8);
         let brick_coords:IVec3 = pos.modulo(8);
 
+        if !self.grid.contains(grid_coords) {
+            return;
+        }
         let brick = self.grid.at(grid_coords.x as usize,grid_coords.y as usize,grid_coords.z as usize);
 
         if *brick == u32::MAX {
@@ -76,12 +129,49 @@ impl BrickMap {
             let mut out = [[[ Voxel{ data: 0 , color: utils::simple_rng_u32()} ;BRICK_SIZE];BRICK_SIZE];BRICK_SIZE];
             out[brick_coords.x as usize][brick_coords.y as usize][brick_coords.z as usize] = voxel;
             self.data.push(out);
+            if self.uploaded {
+                self.dirty_cells.insert(self.grid.index(grid_coords));
+            }
         } else {
-            let data = &mut self.data[*brick as usize];
+            let brick = *brick as usize;
+            let data = &mut self.data[brick];
             data[brick_coords.x as usize][brick_coords.y as usize][brick_coords.z as usize] = voxel;
+            if brick < self.uploaded_bricks {
+                self.dirty_bricks.insert(brick);
+            }
         }
     }
-    pub unsafe fn gen_ssbos(&self) -> (u32,u32) {
+    /// Returns `None` for empty voxels
+    pub fn get_voxel(&self, pos: IVec3) -> Option<Voxel> {
+        let grid_coords :IVec3 = pos.div_floor(8);
+        let brick_coords:IVec3 = pos.modulo(8);
+
+        let brick = self.grid.get(grid_coords);
+        if brick == u32::MAX {
+            return None;
+        }
+        let voxel = self.data[brick as usize][brick_coords.x as usize][brick_coords.y as usize][brick_coords.z as usize];
+        if voxel.data == 0 {
+            None
+        } else {
+            Some(voxel)
+        }
+    }
+    /// Clears the voxel, the brick stays allocated even if it ends up empty
+    pub fn remove_voxel(&mut self, pos: IVec3) {
+        let grid_coords :IVec3 = pos.div_floor(8);
+        let brick_coords:IVec3 = pos.modulo(8);
+
+        let brick = self.grid.get(grid_coords);
+        if brick == u32::MAX {
+            return;
+        }
+        self.data[brick as usize][brick_coords.x as usize][brick_coords.y as usize][brick_coords.z as usize].data = 0;
+        if (brick as usize) < self.uploaded_bricks {
+            self.dirty_bricks.insert(brick as usize);
+        }
+    }
+    pub unsafe fn gen_ssbos(&mut self) -> (u32,u32) {
         use std::mem;
 
         let mut brick_grid_ssbo = 0;
@@ -125,9 +215,85 @@ impl BrickMap {
         }
 
         gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+
+        self.uploaded = true;
+        self.dirty_cells.clear();
+        self.dirty_bricks.clear();
+        self.uploaded_bricks = self.data.len();
+        self.data_ssbo_capacity = self.data.len();
         
         (brick_grid_ssbo, brick_data_ssbo)
     }
+    /// Uploads only what changed since the last upload. When bricks were appended past the
+    /// capacity of the data ssbo it's replaced by one twice as big, so `brick_data_ssbo` can change
+    pub unsafe fn update_ssbos(&mut self, brick_grid_ssbo: u32, brick_data_ssbo: &mut u32) {
+        use std::mem;
+        let brick_size = mem::size_of::<self::Brick>();
+
+        if self.data.len() > self.data_ssbo_capacity {
+            let new_capacity = self.data.len().max(self.data_ssbo_capacity * 2);
+
+            let mut new_ssbo = 0;
+            gl::GenBuffers(1, &mut new_ssbo);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, new_ssbo);
+            gl::BufferData(
+                gl::COPY_WRITE_BUFFER,
+                (new_capacity * brick_size) as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::COPY_READ_BUFFER, *brick_data_ssbo);
+            gl::CopyBufferSubData(
+                gl::COPY_READ_BUFFER,
+                gl::COPY_WRITE_BUFFER,
+                0,
+                0,
+                (self.uploaded_bricks * brick_size) as isize,
+            );
+            gl::DeleteBuffers(1, brick_data_ssbo);
+
+            *brick_data_ssbo = new_ssbo;
+            self.data_ssbo_capacity = new_capacity;
+        }
+
+        // Changed bricks and the appended ones
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, *brick_data_ssbo);
+        let appended = self.uploaded_bricks..self.data.len();
+        for range in contiguous_ranges(self.dirty_bricks.iter().copied().chain(appended)) {
+            gl::BufferSubData(
+                gl::SHADER_STORAGE_BUFFER,
+                (range.start * brick_size) as isize,
+                (range.len() * brick_size) as isize,
+                self.data[range].as_ptr() as *const _,
+            );
+        }
+
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, brick_grid_ssbo);
+        for range in contiguous_ranges(self.dirty_cells.iter().copied()) {
+            gl::BufferSubData(
+                gl::SHADER_STORAGE_BUFFER,
+                (range.start * mem::size_of::<u32>()) as isize,
+                (range.len() * mem::size_of::<u32>()) as isize,
+                self.grid.arr[range].as_ptr() as *const _,
+            );
+        }
+        gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+
+        self.dirty_cells.clear();
+        self.dirty_bricks.clear();
+        self.uploaded_bricks = self.data.len();
+    }
+}
+/// Groups sorted indices into ranges of consecutive ones, so each can be a single upload
+fn contiguous_ranges(indices: impl Iterator<Item = usize>) -> Vec<std::ops::Range<usize>> {
+    let mut out: Vec<std::ops::Range<usize>> = Vec::new();
+    for i in indices {
+        match out.last_mut() {
+            Some(range) if range.end == i => range.end += 1,
+            _ => out.push(i..i + 1),
+        }
+    }
+    out
 }
 
 
@@ -294,7 +460,11 @@ pub fn gen_chunk_octree_2d() -> Octree {
             let max_y = get_height(x,z) * 10. + 10.;
             let mut y = 0;
             while (y as f32) < max_y {
-                octree.add_block(ivec3!(x,y,z));
+                let mut color = RED;
+                if ((x / 8) % 2 == 0) ^ ((z / 8) %2 == 0) ^ ((y / 8) %2 == 0){
+                    color.ch.g = 0b00111111;
+                }
+                octree.add_block(ivec3!(x,y,z), unsafe{color.col});
                 y += 1;
             }
         }
@@ -326,7 +496,7 @@ pub fn gen_chunk_octree() -> Octree {
         for y in 0..SIZE as i32{
             for z in 0..SIZE as i32{
                 if has_voxel(x,y,z) {
-                    octree.add_block(ivec3!(x,y,z));
+                    octree.add_block(ivec3!(x,y,z),utils::simple_rng_u32());
                 }
             }
         }
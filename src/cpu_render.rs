@@ -0,0 +1,706 @@
+use my_math::prelude::*;
+
+use crate::camera::Camera;
+use crate::chunk::{self,BrickMap,Chunk};
+use crate::entity::{self,Entity};
+use crate::ray::{self,RayHit};
+use crate::render::{self,DebugView,LocalTransform,RenderSettings,ToneMap};
+use crate::sky;
+use crate::world::World;
+
+/// Depth of pixels nothing was drawn to, same as `FAR_DEPTH` in `clear_texture.comp`
+pub const FAR_DEPTH: f32 = 1e30;
+
+/// RGBA float image laid out like the RGBA32F screen texture, row 0 is the bottom of the screen
+#[derive(Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32;4]>,
+}
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![[0.;4]; (width * height) as usize],
+        }
+    }
+    pub fn get(&self, x: u32, y: u32) -> [f32;4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+    pub fn set(&mut self, x: u32, y: u32, pixel: [f32;4]) {
+        self.pixels[(y * self.width + x) as usize] = pixel;
+    }
+    /// 8 bit RGB rows from the top of the screen down, channels are clamped to [0,1]
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity((self.width * self.height * 3) as usize);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let pixel = self.get(x,y);
+                out.extend(pixel[..3].iter().map(|c| (c.clamp(0.,1.) * 255.).round() as u8));
+            }
+        }
+        out
+    }
+    /// Binary PPM (P6)
+    pub fn write_ppm(&self, path: &std::path::Path) -> std::io::Result<()> {
+        use std::io::Write;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write!(file,"P6\n{} {}\n255\n",self.width,self.height)?;
+        file.write_all(&self.to_rgb8())?;
+        file.flush()
+    }
+    /// Inverse of `to_rgb8`, alpha is 1
+    pub fn from_rgb8(width: u32, height: u32, rgb: &[u8]) -> Self {
+        assert_eq!(rgb.len(), (width * height * 3) as usize, "wrong number of bytes for the size");
+        let mut image = Image::new(width,height);
+        for (i,pixel) in rgb.chunks_exact(3).enumerate() {
+            let x = i as u32 % width;
+            let y = height - 1 - i as u32 / width;
+            image.set(x,y,[pixel[0] as f32 / 255.,pixel[1] as f32 / 255.,pixel[2] as f32 / 255.,1.]);
+        }
+        image
+    }
+    /// Reads the binary PPMs `write_ppm` writes
+    pub fn read_ppm(path: &std::path::Path) -> std::io::Result<Self> {
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData,format!("{}: {msg}",path.display()));
+        let data = std::fs::read(path)?;
+        // magic, size, max value and the pixels
+        let mut parts = data.splitn(4,|b| *b == b'\n');
+        let (Some(magic),Some(size),Some(max),Some(rgb)) = (parts.next(),parts.next(),parts.next(),parts.next()) else {
+            return Err(invalid("truncated header"));
+        };
+        if magic != b"P6" || max != b"255" {
+            return Err(invalid("not an 8 bit binary PPM"));
+        }
+        let size: Vec<u32> = String::from_utf8_lossy(size).split_whitespace().filter_map(|n| n.parse().ok()).collect();
+        let [width,height] = size[..] else {
+            return Err(invalid("bad size"));
+        };
+        if rgb.len() != (width * height * 3) as usize {
+            return Err(invalid("wrong number of pixels"));
+        }
+        Ok(Image::from_rgb8(width,height,rgb))
+    }
+    /// Largest difference of any channel, for comparing against golden images
+    pub fn max_diff(&self, other: &Image) -> f32 {
+        assert!(self.width == other.width && self.height == other.height, "image sizes differ");
+        self.pixels.iter().zip(&other.pixels)
+            .flat_map(|(a,b)| (0..4).map(move |i| (a[i] - b[i]).abs()))
+            .fold(0.,f32::max)
+    }
+}
+
+/// Renders the same frame as `render::Renderer`, every pixel keeps the closest hit
+pub fn render_frame(
+    world:      &World,
+    entity:     Option<&Entity>,
+    camera:     &Camera,
+    settings:   &RenderSettings,
+    width:      u32,
+    height:     u32,
+    ) -> Image
+{
+    render_frame_with_depth(world,entity,camera,settings,width,height).0
+}
+
+/// `render_frame` that also returns the depth image, laid out like the pixels
+pub fn render_frame_with_depth(
+    world:      &World,
+    entity:     Option<&Entity>,
+    camera:     &Camera,
+    settings:   &RenderSettings,
+    width:      u32,
+    height:     u32,
+    ) -> (Image,Vec<f32>)
+{
+    render_pixels(world,entity,camera,settings,width,height,|scene,x,y| scene.trace_pixel(x,y,width,height))
+}
+
+/// Same as `samples` frames of `render::Renderer` with path tracing on, averaged like the
+/// accumulation buffer. Deterministic for a given `RenderSettings::path_seed`
+pub fn path_trace_frame(
+    world:      &World,
+    entity:     Option<&Entity>,
+    camera:     &Camera,
+    settings:   &RenderSettings,
+    width:      u32,
+    height:     u32,
+    samples:    u32,
+    ) -> Image
+{
+    render_pixels(world,entity,camera,settings,width,height,|scene,x,y| {
+        let mut sum = Vec3::ZERO;
+        for sample in 0..samples {
+            sum = sum + scene.path_sample(x,y,width,height,sample);
+        }
+        let pixel = sum * (1. / samples.max(1) as f32);
+        ([pixel.x,pixel.y,pixel.z,1.],FAR_DEPTH)
+    }).0
+}
+
+/// Runs `pixel` for every pixel on all cores, it returns the color and the depth
+fn render_pixels(
+    world:      &World,
+    entity:     Option<&Entity>,
+    camera:     &Camera,
+    settings:   &RenderSettings,
+    width:      u32,
+    height:     u32,
+    pixel:      impl Fn(&Scene,u32,u32) -> ([f32;4],f32) + Sync,
+    ) -> (Image,Vec<f32>)
+{
+    let dist_to_camera = |pos: IVec3| {
+        (camera.pos - (pos * chunk::SIZE as i32 + (chunk::SIZE as i32/2)).as_vec3() ).mag()
+    };
+    let mut chunks: Vec<&Chunk> = world.chunks.values().collect();
+    chunks.sort_by(|a,b| {
+        dist_to_camera(a.pos).partial_cmp(&dist_to_camera(b.pos))
+        .expect("Coundnt compare")
+    });
+    let entity_camera = entity.map(|entity| {
+        let (pos,dir) = entity::ray_to_local(entity,camera.pos,camera.dir);
+        (entity,pos,dir)
+    });
+    // Everything that can cast a shadow
+    let mut occluders: Vec<(&BrickMap,LocalTransform)> = chunks.iter()
+        .map(|chunk| (&chunk.brickmap,LocalTransform::chunk(chunk.pos)))
+        .collect();
+    if let Some(entity) = entity {
+        occluders.push((&entity.brickmap,LocalTransform::entity(entity)));
+    }
+    let scene = Scene { chunks: &chunks, entity_camera, occluders: &occluders, camera, settings };
+
+    let mut image = Image::new(width,height);
+    let mut depth = vec![FAR_DEPTH; (width * height) as usize];
+    let thread_count = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let rows_per_thread = (height as usize).div_ceil(thread_count).max(1);
+    let chunk_len = rows_per_thread * width as usize;
+
+    std::thread::scope(|scope| {
+        let rows = image.pixels.chunks_mut(chunk_len).zip(depth.chunks_mut(chunk_len));
+        for (i,(rows,depth_rows)) in rows.enumerate() {
+            let scene = &scene;
+            let pixel_fn = &pixel;
+            scope.spawn(move || {
+                let first_row = (i * rows_per_thread) as u32;
+                for (j,(pixel,depth)) in rows.iter_mut().zip(depth_rows).enumerate() {
+                    let x = j as u32 % width;
+                    let y = first_row + j as u32 / width;
+                    (*pixel,*depth) = pixel_fn(scene,x,y);
+                }
+            });
+        }
+    });
+    (image,depth)
+}
+
+/// The closest hit of a pixel
+#[derive(Debug,Clone,Copy)]
+pub struct Surface {
+    pub dist: f32,
+    pub albedo: Vec3,
+    /// World space
+    pub normal: Vec3,
+    /// 1 is unoccluded
+    pub ao: f32,
+}
+
+struct Scene<'a> {
+    chunks:         &'a [&'a Chunk],
+    entity_camera:  Option<(&'a Entity,Vec3,Vec3)>,
+    occluders:      &'a [(&'a BrickMap,LocalTransform)],
+    camera:         &'a Camera,
+    settings:       &'a RenderSettings,
+}
+impl Scene<'_> {
+    fn trace_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> ([f32;4],f32) {
+        if self.settings.debug_view != DebugView::Off {
+            return self.debug_pixel(x,y,width,height);
+        }
+        let Some((surface,ray_dir)) = self.primary(x,y,width,height) else {
+            return (self.sky_pixel(x,y,width,height),FAR_DEPTH);
+        };
+
+        let to_light = -1. * self.settings.light_dir;
+        let shadowed = self.settings.shadows && surface.normal.dot(to_light) > 0. && {
+            let hit_pos = self.camera.pos + ray_dir * surface.dist + surface.normal * render::SHADOW_BIAS;
+            self.occluded(hit_pos,to_light)
+        };
+        let [r,g,b,a] = shade(&surface,shadowed,self.settings);
+        let pixel = self.fog(vec3!(r,g,b),ray_dir,surface.dist);
+        ([pixel.x,pixel.y,pixel.z,a],surface.dist)
+    }
+    /// Same as the debug views of the trace passes, in the same order as the passes.
+    /// Every brickmap adds its steps, like the passes they skip the ones behind the closest hit so far
+    fn debug_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> ([f32;4],f32) {
+        let camera = self.camera;
+        let view = self.settings.debug_view;
+        // Line width per voxel of distance
+        let line_scale = render::DEBUG_LINE_PIXELS * 2. * (camera.fov * 0.5).to_radians().tan() / height as f32;
+        let mut depth = FAR_DEPTH;
+        let mut steps = 0;
+        let mut color = Vec3::ZERO;
+        let mut trace = |brickmap: &BrickMap, transform: &LocalTransform, local_pos: Vec3, local_dir: Vec3, size: Vec3| {
+            let (hit,hit_steps) = ray::dda_brickmap_steps(local_pos,local_dir,brickmap,depth);
+            steps += hit_steps;
+            let Some(hit) = hit.filter(|hit| hit.dist < depth) else {
+                return;
+            };
+            depth = hit.dist;
+            let surface = self.surface(&hit,brickmap,local_pos,local_dir,transform);
+            let hit_dir: IVec3 = hit.dir.into();
+            let hit_pos = local_pos + local_dir * hit.dist;
+            color = debug_color(view,&surface,hit_pos,hit_dir * -1,size,steps as f32,line_scale * hit.dist);
+        };
+
+        if let Some((entity,local_pos,local_dir)) = self.entity_camera {
+            let ray_dir = gen_ray_dir(local_dir,camera.fov,x,y,width,height);
+            // The chunk view outlines the whole entity
+            trace(&entity.brickmap,&LocalTransform::entity(entity),local_pos,ray_dir,entity.size.as_vec3());
+        }
+        let ray_dir = gen_ray_dir(camera.dir,camera.fov,x,y,width,height);
+        let size = chunk::SIZE as f32;
+        for chunk in self.chunks {
+            let transform = LocalTransform::chunk(chunk.pos);
+            trace(&chunk.brickmap,&transform,transform.point(camera.pos),ray_dir,vec3!(size,size,size));
+        }
+        // Every pass redraws the heatmap, hit or not
+        if view == DebugView::Steps {
+            color = heatmap(steps as f32);
+        }
+        ([color.x,color.y,color.z,1.],depth)
+    }
+    /// Same as the fog in `sky.comp`
+    fn fog(&self, color: Vec3, ray_dir: Vec3, dist: f32) -> Vec3 {
+        let fog = self.settings.fog_amount(dist);
+        if fog == 0. {
+            return color;
+        }
+        color * (1. - fog) + sky::sky_color(ray_dir,self.settings.sun_dir,false) * fog
+    }
+    /// Same as `sky.comp`
+    fn sky_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> [f32;4] {
+        let ray_dir = gen_ray_dir(self.camera.dir,self.camera.fov,x,y,width,height);
+        let sky = sky::sky_color(ray_dir,self.settings.sun_dir,true);
+        [sky.x,sky.y,sky.z,1.]
+    }
+    /// Same as `path_step.comp` and `path_trace.comp`, one sample of the accumulation.
+    /// Pixels without a hit get the sky like the sky pass paints over them
+    fn path_sample(&self, x: u32, y: u32, width: u32, height: u32, sample: u32) -> Vec3 {
+        let settings = self.settings;
+        let to_light = -1. * settings.light_dir;
+        // The light reaching a path vertex directly
+        let direct = |pos: Vec3, normal: Vec3| {
+            let cos = normal.dot(to_light);
+            if cos <= 0. || self.occluded(pos,to_light) { Vec3::ZERO } else { settings.light_color * cos }
+        };
+        let Some((surface,ray_dir)) = self.primary(x,y,width,height) else {
+            let [r,g,b,_] = self.sky_pixel(x,y,width,height);
+            return vec3!(r,g,b);
+        };
+        let mut origin = self.camera.pos + ray_dir * surface.dist + surface.normal * render::SHADOW_BIAS;
+        let mut sum = surface.albedo * direct(origin,surface.normal);
+
+        // The gpu keeps the throughput and the albedo of hits in 8 bits
+        let mut throughput = quantize(surface.albedo);
+        let mut dir = render::cosine_hemisphere(surface.normal,settings.path_seed,sample,(x,y),0);
+        for bounce in 0..settings.path_bounces {
+            let Some(hit) = self.closest_surface(origin,dir) else {
+                sum = sum + throughput * sky::sky_color(dir,settings.sun_dir,false);
+                break;
+            };
+            origin = origin + dir * hit.dist + hit.normal * render::SHADOW_BIAS;
+            let albedo = quantize(hit.albedo);
+            sum = sum + throughput * albedo * direct(origin,hit.normal);
+
+            if bounce + 1 >= settings.path_bounces {
+                break;
+            }
+            throughput = quantize(throughput * albedo);
+            dir = render::cosine_hemisphere(hit.normal,settings.path_seed,sample,(x,y),bounce + 1);
+        }
+        self.fog(sum,ray_dir,surface.dist)
+    }
+    /// Closest hit of the camera ray and its world space direction, what the trace passes find
+    fn primary(&self, x: u32, y: u32, width: u32, height: u32) -> Option<(Surface,Vec3)> {
+        let camera = self.camera;
+        let mut closest: Option<Surface> = None;
+        // The entity transform has no scale so local distances are world distances
+        if let Some((entity,local_pos,local_dir)) = self.entity_camera {
+            let ray_dir = gen_ray_dir(local_dir,camera.fov,x,y,width,height);
+            if let Some(hit) = ray::dda_brickmap(local_pos,ray_dir,&entity.brickmap) {
+                let transform = LocalTransform::entity(entity);
+                closest = Some(self.surface(&hit,&entity.brickmap,local_pos,ray_dir,&transform));
+            }
+        }
+
+        let ray_dir = gen_ray_dir(camera.dir,camera.fov,x,y,width,height);
+        for chunk in self.chunks {
+            let transform = LocalTransform::chunk(chunk.pos);
+            let local_pos = transform.point(camera.pos);
+            let Some(hit) = ray::dda_brickmap(local_pos,ray_dir,&chunk.brickmap) else {
+                continue;
+            };
+            if closest.is_none_or(|c| hit.dist < c.dist) {
+                closest = Some(self.surface(&hit,&chunk.brickmap,local_pos,ray_dir,&transform));
+            }
+        }
+        closest.map(|surface| (surface,ray_dir))
+    }
+    /// What the trace passes write to the g-buffer
+    fn surface(&self, hit: &RayHit, brickmap: &BrickMap, local_pos: Vec3, local_dir: Vec3, transform: &LocalTransform) -> Surface {
+        // The face that was hit points against the step direction
+        let hit_dir: IVec3 = hit.dir.into();
+        let normal = hit_dir * -1;
+        let ao = if self.settings.ambient_occlusion {
+            voxel_ao(brickmap,hit.voxel_pos,normal,local_pos + local_dir * hit.dist)
+        } else {
+            1.
+        };
+        Surface {
+            dist: hit.dist,
+            albedo: decode_color(hit.color),
+            normal: transform.dir_to_world(normal.as_vec3()),
+            ao,
+        }
+    }
+    /// Same as the closest hit mode of `path_trace.comp` run over every brickmap, without ambient occlusion
+    fn closest_surface(&self, pos: Vec3, dir: Vec3) -> Option<Surface> {
+        let mut closest: Option<Surface> = None;
+        for (brickmap,transform) in self.occluders {
+            let Some(hit) = ray::dda_brickmap(transform.point(pos),transform.dir(dir),brickmap) else {
+                continue;
+            };
+            if closest.is_none_or(|c| hit.dist < c.dist) {
+                let hit_dir: IVec3 = hit.dir.into();
+                closest = Some(Surface {
+                    dist: hit.dist,
+                    albedo: decode_color(hit.color),
+                    normal: transform.dir_to_world(-1. * hit_dir.as_vec3()),
+                    ao: 1.,
+                });
+            }
+        }
+        closest
+    }
+    /// Same as `shadow.comp` run over every brickmap
+    fn occluded(&self, pos: Vec3, dir: Vec3) -> bool {
+        self.occluders.iter().any(|(brickmap,transform)| {
+            ray::dda_brickmap(transform.point(pos),transform.dir(dir),brickmap).is_some()
+        })
+    }
+}
+
+/// Same as the ray generation at the top of the trace shaders
+pub fn gen_ray_dir(camera_dir: Vec3, fov: f32, x: u32, y: u32, width: u32, height: u32) -> Vec3 {
+    let (res_x,res_y) = (width as f32, height as f32);
+    let ndc_x = (x as f32 * 2. - res_x) / res_x * (res_x / res_y);
+    let ndc_y = (y as f32 * 2. - res_y) / res_y;
+
+    let forward = camera_dir;
+    let left    = forward.cross(Vec3::Y).norm();
+    let up      = left.cross(forward);
+
+    let scale = (fov * 0.5).to_radians().tan();
+    (forward - (ndc_x * scale) * left + (ndc_y * scale) * up).norm()
+}
+
+/// Same as `shade.comp`
+pub fn shade(surface: &Surface, shadowed: bool, settings: &RenderSettings) -> [f32;4] {
+    let mut ratio = (settings.light_dir.dot(-1. * surface.normal) + 1.0) / 2.0;
+    if shadowed {
+        ratio = ratio.min(render::SHADOW_LIGHT);
+    }
+    let occlusion = 1. - settings.ao_strength() * (1. - surface.ao);
+    let albedo = surface.albedo;
+    let ambient = render::AMBIENT * sky::sky_ambient(surface.normal,settings.sun_dir);
+    let pixel = (albedo * ratio * settings.light_color + albedo * ambient) * occlusion;
+    [pixel.x,pixel.y,pixel.z,1.]
+}
+
+/// Same as `voxel_ao` in the trace shaders. Every corner of the face looks at the two edge
+/// neighbours and the corner neighbour in front of it, interpolated with `hit_pos` in local coordinates
+pub fn voxel_ao(brickmap: &BrickMap, voxel_pos: IVec3, normal: IVec3, hit_pos: Vec3) -> f32 {
+    let solid = |pos: IVec3| brickmap.get_voxel(pos).is_some();
+    // The two axes along the face
+    let t = if normal.x != 0 { ivec3!(0,1,0) } else { ivec3!(1,0,0) };
+    let b = if normal.z != 0 { ivec3!(0,1,0) } else { ivec3!(0,0,1) };
+    let front = voxel_pos + normal;
+
+    let mut corners = [0.;4];
+    for (i,corner_ao) in corners.iter_mut().enumerate() {
+        let st = if i & 1 != 0 { 1 } else { -1 };
+        let sb = if i & 2 != 0 { 1 } else { -1 };
+        let side1  = solid(front + t * st);
+        let side2  = solid(front + b * sb);
+        let corner = solid(front + t * st + b * sb);
+        let ao = if side1 && side2 { 0 } else { 3 - (side1 as i32 + side2 as i32 + corner as i32) };
+        *corner_ao = ao as f32 / 3.;
+    }
+    let local = hit_pos - voxel_pos.as_vec3();
+    let u = local.dot(t.as_vec3()).clamp(0.,1.);
+    let v = local.dot(b.as_vec3()).clamp(0.,1.);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(lerp(corners[0],corners[1],u), lerp(corners[2],corners[3],u), v)
+}
+
+/// Same as `present.frag` without the sRGB encoding, which `screenshot::to_srgb8` does
+pub fn tone_map(image: &Image, settings: &RenderSettings) -> Image {
+    let mut out = image.clone();
+    for pixel in out.pixels.iter_mut() {
+        for c in pixel[..3].iter_mut() {
+            // The debug views show their colors as they are
+            if settings.debug_view != DebugView::Off {
+                *c = c.clamp(0.,1.);
+                continue;
+            }
+            let x = *c * settings.exposure;
+            *c = match settings.tone_map {
+                // Narkowicz's fit of the ACES filmic curve
+                ToneMap::Aces => ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.,1.),
+                ToneMap::Reinhard => x / (1. + x),
+            };
+        }
+        pixel[3] = 1.;
+    }
+    out
+}
+
+/// Same as `debug_color` in the trace shaders. `hit_pos` and `local_normal` are in the local
+/// coordinates of the brickmap, which is one `chunk_size` box
+pub fn debug_color(
+    view:           DebugView,
+    surface:        &Surface,
+    hit_pos:        Vec3,
+    local_normal:   IVec3,
+    chunk_size:     Vec3,
+    steps:          f32,
+    line_width:     f32,
+    ) -> Vec3
+{
+    let brick = chunk::BRICK_SIZE as f32;
+    match view {
+        DebugView::Off | DebugView::Albedo => surface.albedo,
+        DebugView::Steps => heatmap(steps),
+        DebugView::Normals => surface.normal * 0.5 + vec3!(0.5,0.5,0.5),
+        DebugView::Bricks if grid_edge_dist(hit_pos,vec3!(brick,brick,brick),local_normal) < line_width => render::BRICK_LINE,
+        DebugView::Chunks if grid_edge_dist(hit_pos,chunk_size,local_normal) < line_width => render::CHUNK_LINE,
+        DebugView::Bricks | DebugView::Chunks => surface.albedo,
+        DebugView::Depth => {
+            let brightness = render::DEPTH_VIEW_HALF / (surface.dist + render::DEPTH_VIEW_HALF);
+            vec3!(brightness,brightness,brightness)
+        }
+    }
+}
+
+/// Same as `heatmap` in the trace shaders, black through blue, green and yellow to red at `render::HEATMAP_STEPS`
+pub fn heatmap(steps: f32) -> Vec3 {
+    let t = (steps / render::HEATMAP_STEPS).clamp(0.,1.) * 4.;
+    let i = (t as usize).min(3);
+    sky::mix(render::HEATMAP[i],render::HEATMAP[i + 1],t - i as f32)
+}
+
+/// Same as `grid_edge_dist` in the trace shaders, distance from `pos` on a face to the closest
+/// line of a grid of `cell` sized boxes. The face itself lies in a plane along its normal
+fn grid_edge_dist(pos: Vec3, cell: Vec3, normal: IVec3) -> f32 {
+    let axis = |p: f32, cell: f32, normal: i32| {
+        if normal != 0 { f32::MAX } else { (p - (p / cell).round() * cell).abs() }
+    };
+    axis(pos.x,cell.x,normal.x).min(axis(pos.y,cell.y,normal.y)).min(axis(pos.z,cell.z,normal.z))
+}
+
+/// Rounds every channel to 8 bits like `packUnorm4x8`
+fn quantize(color: Vec3) -> Vec3 {
+    let channel = |c: f32| (c.clamp(0.,1.) * 255.).round() / 255.;
+    vec3!(channel(color.x),channel(color.y),channel(color.z))
+}
+
+/// The channels in the order the shaders unpack them, from 8 bit sRGB to linear
+pub fn decode_color(color: u32) -> Vec3 {
+    let channel = |c: u32| srgb_to_linear((c & 0xFF) as f32 / 255.);
+    vec3!(channel(color), channel(color >> 8), channel(color >> 16))
+}
+
+/// Same as `srgb_to_linear` in the trace shaders
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{BrickMap,Voxel};
+    use std::path::PathBuf;
+
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 48;
+    /// One step of 8 bit color, so rounding differences between platforms still pass
+    const GOLDEN_TOLERANCE: f32 = 1.5 / 255.;
+
+    /// A floor with a box and a ball on it, all in a small brickmap of the chunk at the origin
+    fn test_world() -> World {
+        let mut brickmap = BrickMap::new(ivec3!(32,32,32));
+        let floor   = Voxel { data: 1, color: 0x80_90_a0 };
+        let pillar  = Voxel { data: 1, color: 0x30_40_d0 };
+        let ball    = Voxel { data: 1, color: 0x40_c0_50 };
+        for x in 0..32 {
+            for z in 0..32 {
+                brickmap.add_voxel(ivec3!(x,0,z),floor);
+                brickmap.add_voxel(ivec3!(x,1,z),floor);
+            }
+        }
+        for x in 6..12 {
+            for y in 2..16 {
+                for z in 8..13 {
+                    brickmap.add_voxel(ivec3!(x,y,z),pillar);
+                }
+            }
+        }
+        for x in 16..30 {
+            for y in 2..16 {
+                for z in 14..28 {
+                    let d = ivec3!(x,y,z).as_vec3() + 0.5 - vec3!(23.,8.,21.);
+                    if d.dot(d) < 36. {
+                        brickmap.add_voxel(ivec3!(x,y,z),ball);
+                    }
+                }
+            }
+        }
+        let mut world = World::new();
+        world.insert_chunk(Chunk { brickmap, brickmap_grid_ssbo: 0, brickmap_data_ssbo: 0, pos: ivec3!(0,0,0), dirty: false });
+        world
+    }
+    /// `entity::build_entity` without the rotation, next to the ball
+    fn test_entity() -> Entity {
+        let mut entity = entity::build_entity();
+        entity.pos = vec3!(2.,2.,20.);
+        entity.orientation = Quaternion::from_axis_angle(Vec3::Y,0.);
+        entity
+    }
+    fn camera(pos: Vec3, target: Vec3) -> Camera {
+        Camera { pos, dir: (target - pos).norm(), ..Camera::default() }
+    }
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{name}.ppm"))
+    }
+    /// Compares the tone mapped image against `tests/golden/<name>.ppm`, running the tests with
+    /// `UPDATE_GOLDEN=1` writes the references instead
+    fn check_golden(name: &str, image: &Image, settings: &RenderSettings) {
+        let image = tone_map(image,settings);
+        let path = golden_path(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.write_ppm(&path).unwrap();
+            return;
+        }
+        let golden = Image::read_ppm(&path)
+            .unwrap_or_else(|err| panic!("{err}, run the tests with UPDATE_GOLDEN=1 to create it"));
+        let image = Image::from_rgb8(image.width,image.height,&image.to_rgb8());
+        let diff = golden.max_diff(&image);
+        assert!(diff <= GOLDEN_TOLERANCE, "{name} differs from the golden image by {diff}");
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let mut image = Image::new(3,2);
+        image.pixels.fill([0.,0.,0.,1.]);
+        image.set(0,0,[1.,0.,0.,1.]);
+        image.set(2,1,[0.,0.5,1.,1.]);
+        let path = std::env::temp_dir().join(format!("cpu_render_round_trip_{}.ppm",std::process::id()));
+        image.write_ppm(&path).unwrap();
+        let read = Image::read_ppm(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(read.max_diff(&image) <= 1. / 255.);
+    }
+    #[test]
+    fn golden_overview() {
+        let settings = RenderSettings::default();
+        let camera = camera(vec3!(-12.,20.,-10.),vec3!(16.,4.,16.));
+        check_golden("overview",&render_frame(&test_world(),None,&camera,&settings,WIDTH,HEIGHT),&settings);
+    }
+    #[test]
+    fn golden_top_down() {
+        let settings = RenderSettings::default();
+        let camera = camera(vec3!(16.,40.,15.),vec3!(16.,0.,17.));
+        check_golden("top_down",&render_frame(&test_world(),None,&camera,&settings,WIDTH,HEIGHT),&settings);
+    }
+    #[test]
+    fn golden_entity() {
+        let settings = RenderSettings::default();
+        let entity = test_entity();
+        let camera = camera(vec3!(-20.,12.,30.),vec3!(10.,6.,22.));
+        check_golden("entity",&render_frame(&test_world(),Some(&entity),&camera,&settings,WIDTH,HEIGHT),&settings);
+    }
+    #[test]
+    fn golden_no_shadows_or_ao() {
+        let settings = RenderSettings { shadows: false, ambient_occlusion: false, ..RenderSettings::default() };
+        let camera = camera(vec3!(40.,6.,4.),vec3!(16.,6.,16.));
+        check_golden("no_shadows_or_ao",&render_frame(&test_world(),None,&camera,&settings,WIDTH,HEIGHT),&settings);
+    }
+    /// Average difference over the color channels
+    fn mean_diff(a: &Image, b: &Image) -> f32 {
+        let sum: f32 = a.pixels.iter().zip(&b.pixels)
+            .map(|(a,b)| (0..3).map(|i| (a[i] - b[i]).abs()).sum::<f32>())
+            .sum();
+        sum / (a.pixels.len() * 3) as f32
+    }
+    #[test]
+    fn path_trace_same_seed_same_image() {
+        let world = test_world();
+        let camera = camera(vec3!(-12.,20.,-10.),vec3!(16.,4.,16.));
+        let settings = RenderSettings { path_tracing: true, path_seed: 3, ..RenderSettings::default() };
+        let first = path_trace_frame(&world,None,&camera,&settings,32,24,4);
+        let second = path_trace_frame(&world,None,&camera,&settings,32,24,4);
+        assert_eq!(first.max_diff(&second), 0.);
+
+        let other_seed = RenderSettings { path_seed: 4, ..settings };
+        let other = path_trace_frame(&world,None,&camera,&other_seed,32,24,4);
+        assert!(first.max_diff(&other) > 0.);
+    }
+    #[test]
+    fn path_trace_converges() {
+        let world = test_world();
+        let camera = camera(vec3!(-12.,20.,-10.),vec3!(16.,4.,16.));
+        let settings = RenderSettings { path_tracing: true, path_seed: 1, ..RenderSettings::default() };
+        let reference = path_trace_frame(&world,None,&camera,&settings,24,16,512);
+
+        // different seeds so the samples aren't a part of the reference
+        let settings = RenderSettings { path_seed: 2, ..settings };
+        let few = path_trace_frame(&world,None,&camera,&settings,24,16,2);
+        let many = path_trace_frame(&world,None,&camera,&settings,24,16,64);
+        let (few_error,many_error) = (mean_diff(&few,&reference),mean_diff(&many,&reference));
+        assert!(many_error < few_error * 0.5, "{many_error} isn't much closer than {few_error}");
+    }
+    #[test]
+    fn path_trace_without_bounces_is_direct_light() {
+        let mut world = World::new();
+        let mut brickmap = BrickMap::new(ivec3!(32,32,32));
+        let color = 0x80_90_a0;
+        for x in 0..32 {
+            for z in 0..32 {
+                brickmap.add_voxel(ivec3!(x,0,z),Voxel { data: 1, color });
+            }
+        }
+        world.insert_chunk(Chunk { brickmap, brickmap_grid_ssbo: 0, brickmap_data_ssbo: 0, pos: ivec3!(0,0,0), dirty: false });
+
+        let camera = camera(vec3!(16.,10.,15.),vec3!(16.,0.,16.));
+        let light_dir = vec3!(1.,-2.,0.5).norm();
+        let settings = RenderSettings { light_dir, path_tracing: true, path_bounces: 0, fog: false, ..RenderSettings::default() };
+        let image = path_trace_frame(&world,None,&camera,&settings,8,8,3);
+
+        let cos = Vec3::Y.dot(-1. * settings.light_dir);
+        assert!(cos > 0., "the test needs the light above the floor");
+        let expected = decode_color(color) * settings.light_color * cos;
+        for pixel in &image.pixels {
+            for (c,e) in pixel[..3].iter().zip([expected.x,expected.y,expected.z]) {
+                assert!((c - e).abs() < 1e-5, "{pixel:?} != {expected:?}");
+            }
+        }
+    }
+}
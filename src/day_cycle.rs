@@ -0,0 +1,89 @@
+use my_math::prelude::*;
+
+use crate::render::RenderSettings;
+use crate::sky;
+
+/// Hour the clock starts at
+pub const START_TIME: f32 = 10.;
+/// Game hours per real second, a full day takes 4 minutes
+pub const DEFAULT_SPEED: f32 = 0.1;
+/// How far the sun's path leans toward +z, so it never passes straight overhead
+const SUN_TILT: f32 = 0.35;
+
+/// Sunlight with the sun high up
+pub const SUN_LIGHT: Vec3 = Vec3 { x: 1.0, y: 0.97, z: 0.9 };
+/// Sunlight with the sun at the horizon
+pub const SUNSET_LIGHT: Vec3 = Vec3 { x: 1.0, y: 0.55, z: 0.3 };
+/// Moonlight, color times intensity
+pub const MOON_LIGHT: Vec3 = Vec3 { x: 0.12, y: 0.14, z: 0.2 };
+
+/// Time of day clock that drives the sun, the moon and everything lit by them.
+/// The sun rises in +x at 6, peaks at 12 and sets in -x at 18, the moon is always opposite
+#[derive(Debug,Clone,Copy)]
+pub struct DayCycle {
+    /// Hours since midnight, from 0 up to 24
+    pub time: f32,
+    /// Game hours per real second
+    pub speed: f32,
+    pub paused: bool,
+}
+impl Default for DayCycle {
+    fn default() -> Self {
+        DayCycle { time: START_TIME, speed: DEFAULT_SPEED, paused: false }
+    }
+}
+impl DayCycle {
+    /// Advances the clock by `d_t` real milliseconds unless paused
+    pub fn update(&mut self, d_t: f32) {
+        if !self.paused {
+            self.scrub(self.speed * d_t / 1000.);
+        }
+    }
+    /// Moves the clock by `hours`, paused or not
+    pub fn scrub(&mut self, hours: f32) {
+        self.set_time(self.time + hours);
+    }
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(24.);
+    }
+    /// Hours since midnight
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+    /// Direction the sunlight travels
+    pub fn sun_dir(&self) -> Vec3 {
+        let angle = (self.time - 6.) / 24. * std::f32::consts::TAU;
+        -1. * vec3!(angle.cos(), angle.sin(), SUN_TILT).norm()
+    }
+    /// Direction the moonlight travels
+    pub fn moon_dir(&self) -> Vec3 {
+        -1. * self.sun_dir()
+    }
+    /// 0 at night, 1 once the sun is well above the horizon
+    pub fn daylight(&self) -> f32 {
+        sky::smoothstep(0., 0.1, -self.sun_dir().y)
+    }
+    /// Direction and color times intensity of the light that shades and casts shadows,
+    /// the sun while it's up and the moon otherwise. Both fade out at the horizon so the switch is seamless
+    pub fn light(&self) -> (Vec3,Vec3) {
+        let sun_dir = self.sun_dir();
+        let sun_height = -sun_dir.y;
+        if sun_height > 0. {
+            let color = sky::mix(SUNSET_LIGHT, SUN_LIGHT, sky::smoothstep(0., 0.4, sun_height));
+            (sun_dir, color * self.daylight())
+        } else {
+            (self.moon_dir(), MOON_LIGHT * sky::smoothstep(0., 0.1, -sun_height))
+        }
+    }
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        let (light_dir,light_color) = self.light();
+        settings.light_dir = light_dir;
+        settings.light_color = light_color;
+        settings.sun_dir = self.sun_dir();
+    }
+    /// `hh:mm`
+    pub fn clock_string(&self) -> String {
+        let minutes = (self.time * 60.) as u32;
+        format!("{:02}:{:02}",minutes / 60 % 24,minutes % 60)
+    }
+}
@@ -12,6 +12,15 @@ pub struct Entity {
 }
 
 pub fn gen_entity() -> Entity {
+    let mut entity = build_entity();
+    let ( brickmap_grid_ssbo, brickmap_data_ssbo,) = unsafe { entity.brickmap.gen_ssbos() };
+    entity.brickmap_grid_ssbo = brickmap_grid_ssbo;
+    entity.brickmap_data_ssbo = brickmap_data_ssbo;
+    entity
+}
+
+/// Same as `gen_entity` without uploading it, doesn't need a gl context
+pub fn build_entity() -> Entity {
     let pos = vec3!(15.,313.,12.);
     let orientation = Quaternion::from_axis_angle(Vec3::Y,45.);
     let size = ivec3!(8,16,32);
@@ -36,12 +45,10 @@ pub fn gen_entity() -> Entity {
             }
         }
     }
-    let ( brickmap_grid_ssbo, brickmap_data_ssbo,) = unsafe { brickmap.gen_ssbos() };
-
     Entity { 
         brickmap,
-        brickmap_grid_ssbo,
-        brickmap_data_ssbo,
+        brickmap_grid_ssbo: 0,
+        brickmap_data_ssbo: 0,
         pos,
         orientation,
         size,
@@ -0,0 +1,129 @@
+use my_math::prelude::*;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::chunk::{self,Chunk};
+use crate::cpu_render;
+use crate::entity;
+use crate::post;
+use crate::recorder::{RecordFormat,Recorder};
+use crate::render::{DebugView,RenderSettings};
+use crate::world::World;
+
+/// Renders a scripted camera flight with the CPU renderer and writes every frame to disk.
+/// Selected with `--headless`, the other flags are optional:
+/// `--frames N --out DIR --width W --height H --radius R --format png|ppm|y4m`
+pub struct HeadlessOptions {
+    pub frames: u32,
+    pub out_dir: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// In chunks, same as `CHUNK_RADIUS`
+    pub radius: f32,
+    pub format: RecordFormat,
+}
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        HeadlessOptions {
+            frames: 60,
+            out_dir: PathBuf::from("./headless_out"),
+            width: crate::WIDTH / 2,
+            height: crate::HEIGHT / 2,
+            radius: crate::CHUNK_RADIUS,
+            format: RecordFormat::Ppm,
+        }
+    }
+}
+impl HeadlessOptions {
+    pub fn from_args(args: &[String]) -> Result<Self,String> {
+        fn parse<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T,String> {
+            let value = value.ok_or(format!("missing value for {flag}"))?;
+            value.parse().map_err(|_| format!("invalid value for {flag}: \"{value}\""))
+        }
+
+        let mut options = HeadlessOptions::default();
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => (),
+                "--frames" => options.frames  = parse(arg,args.next())?,
+                "--out"    => options.out_dir = parse(arg,args.next())?,
+                "--width"  => options.width   = parse(arg,args.next())?,
+                "--height" => options.height  = parse(arg,args.next())?,
+                "--radius" => options.radius  = parse(arg,args.next())?,
+                "--format" => options.format  = parse(arg,args.next())?,
+                _ => return Err(format!("unknown argument \"{arg}\"")),
+            }
+        }
+        if options.width == 0 || options.height == 0 {
+            return Err("the resolution can't be zero".into());
+        }
+        Ok(options)
+    }
+}
+
+/// Degrees the camera turns every frame
+const TURN_SPEED: f32 = 0.5;
+
+pub fn run(options: &HeadlessOptions) -> Result<(),String> {
+    // Y4m goes to a single file next to where the numbered frames would be
+    let out_path = match options.format {
+        RecordFormat::Y4m => options.out_dir.join("recording"),
+        _ => options.out_dir.clone(),
+    };
+    let mut recorder = Recorder::with_path(options.format,&out_path,options.width,options.height,crate::RECORD_FPS,1)
+        .map_err(|err| format!("couldn't create {}: {err}",out_path.display()))?;
+
+    let mut camera = crate::start_camera();
+    let mut settings = RenderSettings::default();
+    settings.fit_fog(options.radius);
+
+    let start = Instant::now();
+    let mut world = World::new();
+    for pos in chunk_positions(camera.pos,options.radius) {
+        let brickmap = chunk::gen_brickmap_2d(pos);
+        world.insert_chunk(Chunk { brickmap, brickmap_grid_ssbo: 0, brickmap_data_ssbo: 0, pos, dirty: false });
+    }
+    let entity = entity::build_entity();
+    // Same as the window, a broken lut only loses the grading
+    let lut = post::load_lut(std::path::Path::new(crate::GRADING_LUT)).unwrap_or_else(|err| {
+        use crate::utils::colors::*;
+        println!("{RED}{err}{RESET_COL}");
+        post::Lut::default_grade()
+    });
+    println!("generated {} chunks in {:?}",world.chunks.len(),start.elapsed());
+
+    for frame in 0..options.frames {
+        let frame_start = Instant::now();
+        let image = cpu_render::render_frame(&world,Some(&entity),&camera,&settings,options.width,options.height);
+        // The debug views skip the post passes like `Renderer::render`
+        let image = if settings.debug_view == DebugView::Off { post::apply(&image,&settings.post,&lut) } else { image };
+        let image = cpu_render::tone_map(&image,&settings);
+
+        recorder.capture(&image).map_err(|err| format!("couldn't write frame {frame}: {err}"))?;
+        println!("frame {frame} ({:?})",frame_start.elapsed());
+
+        // Scripted flight: forward while slowly turning
+        camera.pos = camera.pos + camera.speed * recorder.frame_time / 1000. * camera.forward();
+        camera.dir.rot_quat(TURN_SPEED,camera.up);
+    }
+    let path = recorder.path.clone();
+    println!("wrote {} frames to {}",recorder.written,path.display());
+    recorder.finish().map_err(|err| format!("couldn't write {}: {err}",path.display()))
+}
+
+/// Chunk positions within `radius` chunks of `pos`
+fn chunk_positions(pos: Vec3, radius: f32) -> Vec<IVec3> {
+    let center = pos / chunk::SIZE as f32;
+    let mut positions = Vec::new();
+    for x in (center.x - radius).floor() as i32..=(center.x + radius).ceil() as i32 {
+        for z in (center.z - radius).floor() as i32..=(center.z + radius).ceil() as i32 {
+            let dx = x as f32 + 0.5 - center.x;
+            let dz = z as f32 + 0.5 - center.z;
+            if dx*dx + dz*dz <= radius*radius {
+                positions.push(ivec3!(x,0,z));
+            }
+        }
+    }
+    positions
+}
@@ -12,6 +12,18 @@ mod shader;
 mod camera;
 mod octree;
 mod entity;
+mod ray;
+mod world;
+mod cpu_render;
+mod headless;
+mod screenshot;
+mod recorder;
+mod render;
+mod sky;
+mod day_cycle;
+mod post;
+mod resolution;
+mod overlay;
 
 #[macro_use]
 extern crate my_math;
@@ -21,9 +33,6 @@ use glfw::{Context, Key, PWindow};
 use std::{time,thread::{self,JoinHandle}};
 use std::time::{Instant,Duration};
 use std::sync::mpsc;
-use crate::utils::*;
-use crate::vertex::*;
-use crate::mesh::Mesh;
 use crate::chunk::Chunk;
 use std::sync::{Arc,Mutex, atomic::{AtomicBool, Ordering}};
 
@@ -35,17 +44,43 @@ pub const WIDTH: u32 = HEIGHT * 16/9;
 pub const FPS: f64 = f64::MAX;
 pub const CHUNK_RADIUS: f32 = 3.5;
 pub const GENERATOR_THREAD_COUNT: u32 = 2;
+/// Max distance in voxels at which blocks can be placed or broken
+pub const EDIT_REACH: f32 = 128.;
+/// Resolution multiplier of Shift+F2 screenshots, they are saved at the bigger size
+pub const SCREENSHOT_SUPERSAMPLE: u32 = 2;
+/// Limit of the path traced samples a supersampled screenshot renders
+pub const SCREENSHOT_MAX_SAMPLES: u32 = 256;
+/// Simulated frame rate of recordings, independent of how fast frames actually render
+pub const RECORD_FPS: u32 = 60;
+/// Only every Nth recorded frame is written
+pub const RECORD_EVERY_NTH: u32 = 1;
+/// Frame time the dynamic resolution aims for, in milliseconds
+pub const TARGET_FRAME_TIME: f32 = 1000. / 60.;
+/// Color grading lut in the `.cube` format, the built in grade is used when it doesn't exist
+pub const GRADING_LUT: &str = "./grade.cube";
 
 struct AppState {
     window: PWindow,
     camera: Camera,
     d_t: f32,
-    light_dir: Vec3,
+    render: render::RenderSettings,
+    day: day_cycle::DayCycle,
     input: utils::InputTracker,
 
+    /// Outline the chunks and the allocated bricks
     wireframe: bool,
     cursor_enabled: bool,
+    /// Outline the nodes of `debug_octree`
     octree_skeleton: bool,
+    debug_octree: Option<octree::Octree>,
+    /// Supersample factor of the screenshot to take this frame
+    screenshot: Option<u32>,
+    recorder: Option<recorder::Recorder>,
+    /// Position and size to go back to when leaving fullscreen, `None` while windowed
+    windowed: Option<(i32,i32,i32,i32)>,
+    /// Size of the window in pixels, the render targets are this times the resolution scale
+    framebuffer: (u32,u32),
+    resolution: resolution::DynamicResolution,
 }
 impl AppState {
     fn with_window(window: PWindow) -> Self {
@@ -53,75 +88,86 @@ impl AppState {
             window,
             camera: Camera::default(),
             d_t: 1.,
-            light_dir: vec3!(1.,1.,0.).norm(),
+            render: render::RenderSettings::default(),
+            day: day_cycle::DayCycle::default(),
             input: utils::InputTracker::new(),
 
             wireframe: false,
             cursor_enabled:true,
             octree_skeleton: false,
+            debug_octree: None,
+            screenshot: None,
+            recorder: None,
+            windowed: None,
+            framebuffer: (WIDTH,HEIGHT),
+            resolution: resolution::DynamicResolution::new(TARGET_FRAME_TIME),
         }
     }
 }
 
+fn finish_recording(recorder: recorder::Recorder) {
+    println!("recorded {} frames to {}",recorder.written,recorder.path.display());
+    if let Err(err) = recorder.finish() {
+        use crate::utils::colors::*;
+        println!("{RED}recording failed: {err}{RESET_COL}");
+    }
+}
+
 fn clear_screen() {
     use std::io::Write;
     print!("\x1b[2J\x1b[H");
     std::io::stdout().flush().unwrap();
 }
 
+/// Where the camera spawns, shared with the headless mode
+pub fn start_camera() -> Camera {
+    let mut camera = Camera::default();
+    //camera.pos= vec3!(1900./3.5+ 256.0,
+                            //256 as f32 *  1.5,
+                            //1900./3.5+ 512.0);
+    //camera.pos = Vec3 { x: chunk::SIZE as f32 / 2., y: 220.88193, z: chunk::SIZE as f32 / 2.};
+    camera.pos = vec3!(15.,313.,12.);
+    camera.dir = vec3!(1.,0.,0.).norm();
+    camera.speed = 64.;
+    camera
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        let result = headless::HeadlessOptions::from_args(&args).and_then(|options| headless::run(&options));
+        if let Err(err) = result {
+            use crate::utils::colors::*;
+            eprintln!("{RED}headless: {err}{RESET_COL}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let (mut glfw, win, events) = unsafe { utils::init(WIDTH,HEIGHT) };
 
     let mut state = AppState::with_window(win);
-    //state.camera.pos= vec3!(1900./3.5+ 256.0,
-                            //256 as f32 *  1.5,
-                            //1900./3.5+ 512.0);
-    //state.camera.pos = Vec3 { x: chunk::SIZE as f32 / 2., y: 220.88193, z: chunk::SIZE as f32 / 2.};
-    state.camera.pos = vec3!(15.,313.,12.);
-    state.camera.dir = vec3!(1.,0.,0.).norm();
-    state.camera.speed = 64.;
-
-
-    // Load shaders
-    let (screen_texturing_program,dda_program,clear_texture,draw_entity_program) = unsafe {
-        use crate::shader::*;
-        let uv_passthrough_vert         = compile_shader(gl::VERTEX_SHADER,"./shaders/uv_passthrough.vert");
-        let texturig_frag               = compile_shader(gl::FRAGMENT_SHADER,"./shaders/texturing.frag");
-        //let dda_compute_shader          = compile_shader(gl::COMPUTE_SHADER,"./shaders/dda_ray.comp");
-        let dda_compute_shader          = compile_shader(gl::COMPUTE_SHADER,"./shaders/dda_brick.comp");
-        let clear_texture_shader        = compile_shader(gl::COMPUTE_SHADER,"./shaders/clear_texture.comp");
-        let draw_entity_shader          = compile_shader(gl::COMPUTE_SHADER,"./shaders/draw_entity.comp");
-
-        let screen_texturing_program    = ShaderProgram::create_program(uv_passthrough_vert,texturig_frag);
-        let dda_program                 = ShaderProgram::create_compute(dda_compute_shader);
-        let clear_texture               = ShaderProgram::create_compute(clear_texture_shader);
-        let draw_entity_program         = ShaderProgram::create_compute(draw_entity_shader);
-
-        gl::DeleteShader(uv_passthrough_vert);
-        gl::DeleteShader(texturig_frag);
-        gl::DeleteShader(dda_compute_shader);
-        gl::DeleteShader(clear_texture_shader);
-        (screen_texturing_program,dda_program,clear_texture,draw_entity_program)
-    };
+    state.camera = start_camera();
 
-    let mut screen_mesh = Mesh::new();
-    screen_mesh.verts = vec![
-        // Positions                            // Texture Coords
-        UvVertex { pos: Vec2::new(-1.,-1.), uv_pos: Vec2::new( 0.0, 0.0) }, // Top-left;
-        UvVertex { pos: Vec2::new(-1., 1.), uv_pos: Vec2::new( 0.0, 1.0) }, // Top-Right
-        UvVertex { pos: Vec2::new( 1., 1.), uv_pos: Vec2::new( 1.0, 1.0) }, // Bottom-Right
-        UvVertex { pos: Vec2::new( 1.,-1.), uv_pos: Vec2::new( 1.0, 0.0) }, // Bottom-Left
-    ];
-    screen_mesh.indices = vec![
-        0, 1, 2, // First triangle
-        2, 3, 0 // Second triangle
-    ];
-    let screen_vao = unsafe { vao_from_mesh(&screen_mesh) };
-
-    let texture = create_texture(WIDTH,HEIGHT);
-    unsafe { gl::BindImageTexture(0, texture, 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA32F) };
+
+    let mut renderer = unsafe { render::Renderer::new() };
+    match post::load_lut(std::path::Path::new(GRADING_LUT)) {
+        Ok(lut) => unsafe { renderer.set_lut(&lut) },
+        Err(err) => {
+            use crate::utils::colors::*;
+            println!("{RED}{err}{RESET_COL}");
+        }
+    }
+    let mut overlay = unsafe { overlay::Overlay::new() };
+    let (width,height) = state.window.get_framebuffer_size();
+    state.framebuffer = (width as u32,height as u32);
+    unsafe { gl::Viewport(0, 0, width, height) };
+    let (width,height) = state.resolution.render_size(width as u32,height as u32);
+    let mut targets = render::RenderTargets::new(width,height);
+    unsafe { targets.bind() };
 
     state.window.set_size_polling(true);
+    state.window.set_framebuffer_size_polling(true);
     state.window.set_key_polling(true);
     state.window.set_cursor_pos_polling(true);
     state.window.set_mouse_button_polling(true);
@@ -150,7 +196,7 @@ fn main() {
                 out_tx.clone(),
         )).collect();
 
-    let mut chunks: Vec<chunk::Chunk> = Vec::new();
+    let mut world = world::World::new();
     let mut entity = entity::gen_entity();
     println!("{:?}",entity.brickmap.grid.arr.len());
 
@@ -159,14 +205,20 @@ fn main() {
         
         state.input.update(&state.window);
         state.camera.update_with_input(&state.input,state.d_t);
+        // The clock stands still while path tracing, the samples only add up under the same light
+        if !state.render.path_tracing {
+            state.day.update(state.d_t);
+        }
+        state.day.apply(&mut state.render);
         if state.window.get_cursor_mode() == glfw::CursorMode::Disabled {
-            state.window.set_cursor_pos((WIDTH /2 ) as f64, (HEIGHT /2 ) as f64);
+            let (width,height) = state.window.get_size();
+            state.window.set_cursor_pos((width /2 ) as f64, (height /2 ) as f64);
         }
         let camera = &state.camera;
 
         match out_rx.try_recv() {
             Ok(chunk) => {
-                chunks.push(chunk);
+                world.insert_chunk(chunk);
             },
             _ => (),
         }
@@ -184,23 +236,20 @@ fn main() {
             //});
 
             // REMOVE CHUNKS
-            let mut i = 0;
-            while i < chunks.len() {
-                let pos = chunks[i].pos;
+            world.chunks.retain(|pos,chunk| {
                 let dx = pos.x as f32 + 0.5 - camera_pos.x;
                 let dz = pos.z as f32 + 0.5 - camera_pos.z;
                 if (dx*dx + dz*dz) <= r_squared { // CHUNK POS IS STILL VALID
-                    i+=1;
+                    true
                 } else { // REMOVE CHUNK
                     unsafe {
-                    gl::DeleteBuffers(1, &chunks[i].brickmap_grid_ssbo);
-                    gl::DeleteBuffers(1, &chunks[i].brickmap_data_ssbo);
+                    gl::DeleteBuffers(1, &chunk.brickmap_grid_ssbo);
+                    gl::DeleteBuffers(1, &chunk.brickmap_data_ssbo);
                     }
-                    //remove_by_value(&mut target_chunks,&chunks[i].pos);
-                    chunks.swap_remove(i);
                     change_flag = true;
+                    false
                 }
-            }
+            });
             target_chunks.retain(|pos| {
                 let dx = pos.x as f32 + 0.5 - camera_pos.x;
                 let dz = pos.z as f32 + 0.5 - camera_pos.z;
@@ -236,13 +285,14 @@ fn main() {
                 let _ = request_tx.send(pos);
             }
             if change_flag {
-                println!("CHUNK NUMBER: {} TARGER: {}",chunks.len(),target_chunks.len());
+                println!("CHUNK NUMBER: {} TARGER: {}",world.chunks.len(),target_chunks.len());
             }
         }
         
         let dist_to_camera = |pos: IVec3| {
             (camera.pos - (pos * chunk::SIZE as i32 + (chunk::SIZE as i32/2)).as_vec3() ).mag()
         };
+        let mut chunks: Vec<&chunk::Chunk> = world.chunks.values().collect();
         chunks.sort_by(|a,b| {
             dist_to_camera(a.pos).partial_cmp(&dist_to_camera(b.pos))
             .expect("Coundnt compare")
@@ -251,66 +301,120 @@ fn main() {
         // RENDER /////////////////////////////////////////////////////////////////////////////////////////////////////////
 
         unsafe {
-            let (local_ray_pos,local_ray_dir) = entity::ray_to_local(&entity,camera.pos,camera.dir);
-            gl::UseProgram(*draw_entity_program);
-            draw_entity_program.set_ivec3("ENTITY_SIZE",entity.size);
-            draw_entity_program.set_float("fov",camera.fov);
-            draw_entity_program.set_vec3("camera_pos",local_ray_pos);
-            draw_entity_program.set_vec3("camera_dir",local_ray_dir);
-            draw_entity_program.set_vec3("light_dir",state.light_dir);
-            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, entity.brickmap_grid_ssbo);
-            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 3, entity.brickmap_data_ssbo);
-
-            gl::DispatchCompute(WIDTH /16 +1, HEIGHT/16 +1, 1);
-
-            gl::UseProgram(*dda_program);
-            dda_program.set_float("fov",camera.fov);
-            dda_program.set_int("CHUNK_SIZE",chunk::SIZE as i32);
-            dda_program.set_vec3("camera_pos",camera.pos);
-            dda_program.set_vec3("camera_dir",camera.dir);
-            dda_program.set_vec3("light_dir",state.light_dir);
-            
-            // Color texture
-            for chunk in &chunks {
-                dda_program.set_ivec3("CHUNK_POS",chunk.pos);
-
-                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, chunk.brickmap_grid_ssbo);
-                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 3, chunk.brickmap_data_ssbo);
-
-                gl::DispatchCompute(WIDTH /16 +1, HEIGHT/16 +1, 1);
+            renderer.render(&mut targets,camera,&state.render,&entity,&chunks);
+
+            if let Some(factor) = state.screenshot.take() {
+                let (width,height) = state.framebuffer;
+                let image = if factor == 1 && (targets.width,targets.height) == (width,height) {
+                    screenshot::read_texture(targets.color,targets.width,targets.height)
+                } else {
+                    // Trace again at the window size times the factor, whatever the resolution scale is.
+                    // The shaders take the resolution from the image
+                    let mut big_targets = render::RenderTargets::new(width * factor, height * factor);
+                    big_targets.bind();
+                    // Path traced, take as many samples as the image on screen has converged with
+                    let samples = if state.render.path_tracing { targets.samples.clamp(1,SCREENSHOT_MAX_SAMPLES) } else { 1 };
+                    for _ in 0..samples {
+                        renderer.render(&mut big_targets,camera,&state.render,&entity,&chunks);
+                    }
+                    let image = screenshot::read_texture(big_targets.color,big_targets.width,big_targets.height);
+
+                    big_targets.delete();
+                    targets.bind();
+                    image
+                };
+                let image = cpu_render::tone_map(&image,&state.render);
+                match screenshot::save_png(&image,std::path::Path::new(screenshot::SCREENSHOT_DIR)) {
+                    Ok(path) => println!("saved {}",path.display()),
+                    Err(err) => {
+                        use crate::utils::colors::*;
+                        println!("{RED}screenshot failed: {err}{RESET_COL}");
+                    }
+                }
+            }
+
+            if let Some(recorder) = &mut state.recorder {
+                if recorder.wants_frame() {
+                    let image = screenshot::read_texture(targets.color,targets.width,targets.height);
+                    if let Err(err) = recorder.capture(&cpu_render::tone_map(&image,&state.render)) {
+                        use crate::utils::colors::*;
+                        println!("{RED}recording failed: {err}{RESET_COL}");
+                        state.recorder = None;
+                    }
+                } else {
+                    recorder.skip();
+                }
+            }
+
+            renderer.present(&targets,&state.render,state.resolution.upscale);
+            if state.wireframe || state.octree_skeleton {
+                let octree = if state.octree_skeleton { state.debug_octree.as_ref() } else { None };
+                overlay.update(&chunks,camera,state.wireframe,octree);
+                overlay.draw(&targets,camera,state.framebuffer);
             }
-            
-            // Draw texture
-            gl::UseProgram(*screen_texturing_program);
-            screen_vao.draw_elements(gl::TRIANGLES);
-            // Clear texture
-            gl::UseProgram(*clear_texture);
-            gl::DispatchCompute(WIDTH /16 +1, HEIGHT/16 +1, 1);
-            //gl::MemoryBarrier(gl::ALL_BARRIER_BITS);
         }
 
 
         glfw.poll_events();
         for (_ ,event) in glfw::flush_messages(&events) {
             use glfw::WindowEvent;
-            //use glfw::MouseButton;
-            //use glfw::Action;
+            use glfw::MouseButton;
+            use glfw::Action;
             match event {
-                WindowEvent::Size(x, y) => {
-                    unsafe { gl::Viewport(0, 0, x, y); }
+                // The framebuffer can be bigger than the window on high dpi screens
+                WindowEvent::FramebufferSize(width, height) => {
+                    // Minimized windows report 0
+                    if width > 0 && height > 0 && (width as u32,height as u32) != state.framebuffer {
+                        state.framebuffer = (width as u32,height as u32);
+                        let (width,height) = state.resolution.render_size(width as u32,height as u32);
+                        unsafe {
+                            gl::Viewport(0, 0, state.framebuffer.0 as i32, state.framebuffer.1 as i32);
+                            targets.resize(width,height);
+                        }
+                        // Every frame of a recording has the same size
+                        if let Some(recorder) = state.recorder.take() {
+                            println!("the window was resized, stopping the recording");
+                            finish_recording(recorder);
+                        }
+                    }
                 }
-                /*
                 glfw::WindowEvent::MouseButton(button, Action::Press, _) => {
+                    // A free cursor is for the window and the ui, not for editing
+                    if state.window.get_cursor_mode() != glfw::CursorMode::Disabled {
+                        continue;
+                    }
+                    let Some(hit) = world.raycast(state.camera.pos,state.camera.dir,EDIT_REACH) else {
+                        continue;
+                    };
                     match button {
+                        // Break
                         MouseButton::Button1 => {
+                            world.remove_voxel(hit.voxel_pos);
+                            targets.reset_accumulation();
+                        }
+                        // Place on the face that was hit
                         MouseButton::Button2 => {
+                            let dir: IVec3 = hit.dir.into();
+                            let pos = hit.voxel_pos - dir;
+                            let camera_voxel = ivec3!(
+                                state.camera.pos.x.floor() as i32,
+                                state.camera.pos.y.floor() as i32,
+                                state.camera.pos.z.floor() as i32
+                            );
+                            // Don't bury the camera
+                            if pos == camera_voxel {
+                                continue;
+                            }
+                            world.set_voxel(pos, chunk::Voxel { data: 1, color: utils::simple_rng_u32() });
+                            targets.reset_accumulation();
+                        }
                         _ => (),
                     }                
                 }
-                */
                 _ => (),
             }
         }
+        unsafe { world.update_dirty_chunks() };
 
         
         for key in &state.input.just_pressed {
@@ -325,19 +429,156 @@ fn main() {
                 }
                 Key::B => {
                     state.octree_skeleton = !state.octree_skeleton;
+                    if state.octree_skeleton {
+                        // Generated on the first press, it takes a while
+                        state.debug_octree.get_or_insert_with(chunk::gen_chunk_octree_2d);
+                    }
+                    println!("octree outline: {}",state.octree_skeleton);
                 }
-                Key::Y => {
-                    state.wireframe = !state.wireframe;
-                    unsafe { 
-                        if state.wireframe {
-                            gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE); 
-                            gl::Disable(gl::CULL_FACE);
-                        } else {
-                            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL); 
-                            gl::Enable(gl::CULL_FACE);
+                Key::F2 => {
+                    // Taken during the next render, before the texture is cleared
+                    let shift = state.input.pressed.contains(&Key::LeftShift);
+                    state.screenshot = Some(if shift { SCREENSHOT_SUPERSAMPLE } else { 1 });
+                }
+                Key::F4 => {
+                    if let Some(recorder) = state.recorder.take() {
+                        finish_recording(recorder);
+                    } else {
+                        let shift = state.input.pressed.contains(&Key::LeftShift);
+                        let format = if shift { recorder::RecordFormat::Y4m } else { recorder::RecordFormat::Png };
+                        let dir = std::path::Path::new(recorder::RECORDING_DIR);
+                        match recorder::Recorder::start(format,dir,targets.width,targets.height,RECORD_FPS,RECORD_EVERY_NTH) {
+                            Ok(recorder) => {
+                                println!("recording to {}",recorder.path.display());
+                                state.recorder = Some(recorder);
+                            }
+                            Err(err) => {
+                                use crate::utils::colors::*;
+                                println!("{RED}couldn't start recording: {err}{RESET_COL}");
+                            }
+                        }
+                    }
+                }
+                Key::F11 => {
+                    if let Some((x,y,width,height)) = state.windowed.take() {
+                        state.window.set_monitor(glfw::WindowMode::Windowed, x, y, width as u32, height as u32, None);
+                    } else {
+                        let (x,y) = state.window.get_pos();
+                        let (width,height) = state.window.get_size();
+                        let window = &mut state.window;
+                        let fullscreen = glfw.with_primary_monitor(|_, monitor| {
+                            let monitor = monitor?;
+                            let mode = monitor.get_video_mode()?;
+                            window.set_monitor(glfw::WindowMode::FullScreen(monitor), 0, 0, mode.width, mode.height, Some(mode.refresh_rate));
+                            Some(())
+                        });
+                        match fullscreen {
+                            Some(()) => state.windowed = Some((x,y,width,height)),
+                            None => {
+                                use crate::utils::colors::*;
+                                println!("{RED}no monitor to go fullscreen on{RESET_COL}");
+                            }
                         }
                     }
                 }
+                // Cycles through automatic and fixed resolution scales
+                Key::F6 => {
+                    state.resolution.fixed = match state.resolution.fixed {
+                        None => Some(1.),
+                        Some(scale) if scale > 0.75 => Some(0.75),
+                        Some(scale) if scale > 0.5 => Some(0.5),
+                        Some(_) => None,
+                    };
+                    match state.resolution.fixed {
+                        Some(scale) => println!("resolution scale: {scale}"),
+                        None => println!("resolution scale: automatic"),
+                    }
+                }
+                Key::F7 => {
+                    state.resolution.upscale = match state.resolution.upscale {
+                        resolution::Upscale::Nearest => resolution::Upscale::Bilinear,
+                        resolution::Upscale::Bilinear => resolution::Upscale::Nearest,
+                    };
+                    println!("upscaling: {:?}",state.resolution.upscale);
+                }
+                Key::F8 => {
+                    state.render.debug_view = state.render.debug_view.next();
+                    println!("debug view: {:?}",state.render.debug_view);
+                }
+                Key::Num1 => {
+                    state.render.shadows = !state.render.shadows;
+                    println!("shadows: {}",state.render.shadows);
+                }
+                Key::Num2 => {
+                    state.render.ambient_occlusion = !state.render.ambient_occlusion;
+                    println!("ambient occlusion: {}",state.render.ambient_occlusion);
+                }
+                Key::Num3 => {
+                    state.render.path_tracing = !state.render.path_tracing;
+                    println!("path tracing: {}",state.render.path_tracing);
+                }
+                Key::Num4 => {
+                    state.render.fog = !state.render.fog;
+                    println!("fog: {}",state.render.fog);
+                }
+                Key::Num5 => {
+                    state.render.tone_map = match state.render.tone_map {
+                        render::ToneMap::Aces => render::ToneMap::Reinhard,
+                        render::ToneMap::Reinhard => render::ToneMap::Aces,
+                    };
+                    println!("tone mapping: {:?}",state.render.tone_map);
+                }
+                // A quarter stop per press
+                Key::Minus | Key::Equal => {
+                    let step = if *key == Key::Minus { -0.25_f32 } else { 0.25 };
+                    state.render.exposure *= step.exp2();
+                    println!("exposure: {:.2}",state.render.exposure);
+                }
+                Key::Num6 => {
+                    state.render.post.bloom = !state.render.post.bloom;
+                    println!("bloom: {}",state.render.post.bloom);
+                }
+                Key::Num7 => {
+                    state.render.post.grading = !state.render.post.grading;
+                    println!("color grading: {}",state.render.post.grading);
+                }
+                Key::Num8 => {
+                    state.render.post.vignette = !state.render.post.vignette;
+                    println!("vignette: {}",state.render.post.vignette);
+                }
+                Key::Num9 => {
+                    state.render.post.sharpen = !state.render.post.sharpen;
+                    println!("sharpening: {}",state.render.post.sharpen);
+                }
+                Key::T => {
+                    state.day.paused = !state.day.paused;
+                    println!("time of day {}: {}",if state.day.paused { "paused" } else { "running" },state.day.clock_string());
+                }
+                Key::Comma | Key::Period => {
+                    state.day.speed *= if *key == Key::Comma { 0.5 } else { 2. };
+                    println!("time of day speed: {} hours per second",state.day.speed);
+                }
+                Key::LeftBracket | Key::RightBracket => {
+                    let step = if *key == Key::LeftBracket { -0.1 } else { 0.1 };
+                    state.render.ao_strength = (state.render.ao_strength + step).clamp(0.,1.);
+                    println!("ambient occlusion strength: {:.1}",state.render.ao_strength);
+                }
+                Key::F3 => {
+                    // Generated on the first press, it takes a while
+                    let octree = state.debug_octree.get_or_insert_with(chunk::gen_chunk_octree_2d);
+                    println!("{:#?}",octree.stats());
+                    match octree.validate() {
+                        Ok(()) => println!("octree is valid"),
+                        Err(err) => {
+                            use crate::utils::colors::*;
+                            println!("{RED}invalid octree: {err}{RESET_COL}");
+                        }
+                    }
+                }
+                Key::Y => {
+                    state.wireframe = !state.wireframe;
+                    println!("chunk and brick outlines: {}",state.wireframe);
+                }
                 _ => (),
             }
         }
@@ -345,9 +586,17 @@ fn main() {
             match key {
                 Key::Escape => state.window.set_should_close(true),
 
-                Key::H => state.light_dir.rot_quat(1. * state.d_t / 16. ,vec3!(-1.,0.,1.)),
+                // Scrubs an hour per second, backward with shift
+                Key::H => {
+                    let shift = state.input.pressed.contains(&Key::LeftShift);
+                    let hours = state.d_t / 1000. * if shift { -1. } else { 1. };
+                    state.day.scrub(hours);
+                }
 
-                Key::U => entity.pos = entity.pos - Vec3::Y * state.d_t / 16.,
+                Key::U => {
+                    entity.pos = entity.pos - Vec3::Y * state.d_t / 16.;
+                    targets.reset_accumulation();
+                }
 
                 _ => (),
             }
@@ -371,9 +620,24 @@ fn main() {
 
         let elapsed = frame_time.elapsed();
         state.d_t = elapsed.as_nanos() as f32 / 1000_000. ; // in millis
+        if let Some(recorder) = &state.recorder {
+            state.d_t = recorder.frame_time;
+        }
         
         let avrg = time_buffer.update(elapsed.as_micros());
-        let fps_string = format!("{:.2}fps ({:.4?})",1./(avrg / 1000_000.),elapsed);
+        // Recordings need a constant size and path tracing restarts on every change
+        let adapt = state.recorder.is_none() && !state.render.path_tracing;
+        if (adapt || state.resolution.fixed.is_some()) && state.resolution.update((avrg / 1000.) as f32, time_buffer.buffer.len() as u32) {
+            let (width,height) = state.resolution.render_size(state.framebuffer.0,state.framebuffer.1);
+            if (width,height) != (targets.width,targets.height) {
+                unsafe { targets.resize(width,height) };
+            }
+        }
+        let mut fps_string = format!("{:.2}fps ({:.4?})",1./(avrg / 1000_000.),elapsed);
+        fps_string += &format!(" {}x{} {}",targets.width,targets.height,state.day.clock_string());
+        if state.render.path_tracing {
+            fps_string += &format!(" {} samples",targets.samples);
+        }
         state.window.set_title(&fps_string);
     }
 
@@ -444,11 +708,11 @@ fn spawn_generator_thread(
             };
             if let Ok(pos) = pos {
                 // Now we have `pos` and can perform the remaining work without holding the lock
-                let brickmap = chunk::gen_brickmap_2d(pos);
+                let mut brickmap = chunk::gen_brickmap_2d(pos);
                 let (brickmap_grid_ssbo, brickmap_data_ssbo) = unsafe { brickmap.gen_ssbos() };
 
                 unsafe { gl::Flush() }; // Finish sending data to ssbo's
-                out_tx.send(Chunk { brickmap, brickmap_data_ssbo, brickmap_grid_ssbo, pos }).unwrap();
+                out_tx.send(Chunk { brickmap, brickmap_data_ssbo, brickmap_grid_ssbo, pos, dirty: false }).unwrap();
             }
         }
     })
@@ -18,9 +18,12 @@ pub struct OctreeNode {
     pub _padding1: [u8;3],
 
     pub position: IVec3,
+
+    /// Color/material of the whole node, only meaningful when `is_full`
+    pub material: u32,
 }
 impl OctreeNode {
-    pub fn new(size: u32,pos: IVec3,full: bool) -> Self {
+    pub fn new(size: u32,pos: IVec3,full: bool,material: u32) -> Self {
         //is_orphan: full,
         OctreeNode {
             children_idx: [0;8],
@@ -33,12 +36,32 @@ impl OctreeNode {
             _padding0: [0;3],
 
             position: pos,
+
+            material: if full { material } else { 0 },
         }
     }
 }
 const ROOT_IDX:usize = 0;
 pub struct Octree {
-    pub nodes: Vec<OctreeNode>
+    pub nodes: Vec<OctreeNode>,
+    /// First index of each group of 8 siblings dropped by a merge, reused by `devide_node`
+    free: Vec<i32>,
+    /// Counts the edits, merges reuse freed nodes so `nodes.len()` often stays the same
+    generation: u64,
+}
+#[derive(Debug,Clone,Copy)]
+pub struct OctreeStats {
+    pub reachable_nodes: usize,
+    /// Nodes waiting in the free list after their parent was merged
+    pub orphaned_nodes: usize,
+    pub max_depth: u32,
+    pub full_leaves: usize,
+    pub empty_leaves: usize,
+    /// Nodes with children
+    pub mixed_nodes: usize,
+    /// In bytes
+    pub mem_used: usize,
+    pub mem_reserved: usize,
 }
 impl Octree {
     pub fn new(size: u32, pos: IVec3) -> Self {
@@ -48,52 +71,82 @@ impl Octree {
             s /= 2;
         }
         Octree {
-            nodes: vec![OctreeNode::new(size,pos,false)],
+            nodes: vec![OctreeNode::new(size,pos,false,0)],
+            free: Vec::new(),
+            generation: 0,
         }
     }
-    pub fn new_full(size: u32, pos: IVec3) -> Self {
+    pub fn new_full(size: u32, pos: IVec3, material: u32) -> Self {
         let mut s = size ;
         while s != 1 {
             assert!(s % 2 == 0, "the size of the quad tree must be a power of two");
             s /= 2;
         }
         Octree {
-            nodes: vec![OctreeNode::new(size,pos,true)],
+            nodes: vec![OctreeNode::new(size,pos,true,material)],
+            free: Vec::new(),
+            generation: 0,
         }
     }
 
-    fn devide_node(&mut self, node_idx: i32,full: bool) {
-        let len = self.nodes.len();
+    fn devide_node(&mut self, node_idx: i32,full: bool,material: u32) {
         let node = &self.nodes[node_idx as usize];
 
         let half_size = (node.size / 2) as i32;
         let pos = node.position;
         let nodes = [
-                OctreeNode::new(half_size as u32,        pos                                                     ,full), 
-                OctreeNode::new(half_size as u32, ivec3!(pos.x            , pos.y            , pos.z + half_size),full),
-                OctreeNode::new(half_size as u32, ivec3!(pos.x            , pos.y + half_size, pos.z            ),full),
-                OctreeNode::new(half_size as u32, ivec3!(pos.x            , pos.y + half_size, pos.z + half_size),full),
-                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y            , pos.z            ),full),
-                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y            , pos.z + half_size),full),
-                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y + half_size, pos.z            ),full),
-                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y + half_size, pos.z + half_size),full),
+                OctreeNode::new(half_size as u32,        pos                                                     ,full,material), 
+                OctreeNode::new(half_size as u32, ivec3!(pos.x            , pos.y            , pos.z + half_size),full,material),
+                OctreeNode::new(half_size as u32, ivec3!(pos.x            , pos.y + half_size, pos.z            ),full,material),
+                OctreeNode::new(half_size as u32, ivec3!(pos.x            , pos.y + half_size, pos.z + half_size),full,material),
+                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y            , pos.z            ),full,material),
+                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y            , pos.z + half_size),full,material),
+                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y + half_size, pos.z            ),full,material),
+                OctreeNode::new(half_size as u32, ivec3!(pos.x + half_size, pos.y + half_size, pos.z + half_size),full,material),
         ];
 
-        // TODO remove orphan nodes
-        self.nodes.extend_from_slice(&nodes);
-        self.nodes[node_idx as usize].children_idx = [0,1,2,3,4,5,6,7].map(|x| (len + x) as i32);
-        return;
+        let start = match self.free.pop() {
+            Some(start) => {
+                self.nodes[start as usize..start as usize + 8].clone_from_slice(&nodes);
+                start as usize
+            }
+            None => {
+                let len = self.nodes.len();
+                self.nodes.extend_from_slice(&nodes);
+                len
+            }
+        };
+        self.nodes[node_idx as usize].children_idx = [0,1,2,3,4,5,6,7].map(|x| (start + x) as i32);
+    }
+    /// Puts the children of a node and everything below them on the free list, the caller still
+    /// has to clear `has_children`
+    fn free_children(&mut self, node_idx: i32) {
+        let node = &self.nodes[node_idx as usize];
+        if !node.has_children {
+            return;
+        }
+        let children_idx = node.children_idx;
+        for child_idx in children_idx {
+            self.free_children(child_idx);
+        }
+        self.free.push(children_idx[0]);
+    }
+
+    /// Changes after every edit of the tree
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 
-    pub fn add_block(&mut self,pos:IVec3) -> bool {
+    pub fn add_block(&mut self,pos:IVec3,material: u32) -> bool {
         if !inside_bouds(&self.nodes[ROOT_IDX],pos) {
             return false;
         }
+        self.generation += 1;
 
-        unsafe { add_block_recursion(self,pos,ROOT_IDX as i32) };
+        unsafe { add_block_recursion(self,pos,material,ROOT_IDX as i32) };
         return true;
 
-        unsafe fn add_block_recursion(tree: &mut Octree,pos: IVec3,node_idx:i32) {
+        unsafe fn add_block_recursion(tree: &mut Octree,pos: IVec3,material: u32,node_idx:i32) {
             //println!("add block {:?}",node_idx);
             // so rust wont complain, makes the code cleaner no need to index into tree.nodes every time
             let curr_node: &mut OctreeNode = &mut *((&mut tree.nodes[node_idx as usize]) as *mut _);
@@ -101,39 +154,48 @@ impl Octree {
             if curr_node.size == 1 {
                 assert!(!curr_node.has_children);
                 curr_node.is_full = true;
+                curr_node.material = material;
                 return;
             } 
-            if curr_node.is_full {
+            if curr_node.is_full && curr_node.material == material {
                 assert!(!curr_node.has_children);
                 return;
             }
 
             if !curr_node.has_children {
-                // is a leaf and not full so its empty => devide and call recursively
+                // is a leaf and either empty or full of a different material => devide and call
+                // recursively, the children inherit the old content
+                let was_full = curr_node.is_full;
+                let old_material = curr_node.material;
                 curr_node.has_children = true;
-                tree.devide_node(node_idx,false);
+                curr_node.is_full = false;
+                curr_node.material = 0;
+                tree.devide_node(node_idx,was_full,old_material);
                 let curr_node: &mut OctreeNode = &mut *((&mut tree.nodes [node_idx as usize]) as *mut _);
 
                 let child_idx = pos_to_idx(curr_node,pos);
 
-                // is empty so one additional voxel cant make it full
-                add_block_recursion(tree,pos,child_idx);
+                // the other seven children still hold the old content so the node cant be merged
+                add_block_recursion(tree,pos,material,child_idx);
             } else {
                 let children_idx = curr_node.children_idx;
-                // isn't a leaf and isn't full => dont devide call recursively and check if filled if
-                // so merge
+                // isn't a leaf and isn't full => dont devide call recursively and check if filled
+                // with a single material if so merge
                 let child_idx = pos_to_idx(curr_node,pos);
 
-                add_block_recursion(tree,pos,child_idx);
+                add_block_recursion(tree,pos,material,child_idx);
                 let curr_node: &mut OctreeNode = &mut *((&mut tree.nodes[node_idx as usize]) as *mut _);
 
-                // check if full 
+                // check if full and identical
                 for child_idx in children_idx {
-                    if !tree.nodes[child_idx as usize].is_full {
+                    let child = &tree.nodes[child_idx as usize];
+                    if !child.is_full || child.material != material {
                         return;
                     }
                 }
+                tree.free_children(node_idx);
                 curr_node.is_full = true;
+                curr_node.material = material;
                 //curr_node.children_idx = None;
                 curr_node.has_children = false
             }
@@ -143,6 +205,7 @@ impl Octree {
         if !inside_bouds(&self.nodes[ROOT_IDX],pos){
             return false;
         }
+        self.generation += 1;
 
         unsafe { remove_block_recursion(self,pos,ROOT_IDX as i32); }
         return true;
@@ -153,6 +216,7 @@ impl Octree {
             if curr_node.size == 1 {
                 assert!(!curr_node.has_children);
                 curr_node.is_full = false;
+                curr_node.material = 0;
                 return;
             }
             if !curr_node.has_children && !curr_node.is_full {
@@ -162,7 +226,7 @@ impl Octree {
             if !curr_node.has_children {
                 // is full and a leaf => devide and call recursively on the proper node
                 curr_node.has_children = true;
-                tree.devide_node(node_idx,true);
+                tree.devide_node(node_idx,true,curr_node.material);
                 let curr_node: &mut OctreeNode = &mut *((&mut tree.nodes[node_idx as usize]) as *mut _);
 
                 let child_idx = pos_to_idx(curr_node,pos);
@@ -170,6 +234,7 @@ impl Octree {
                 //let children = curr_node.children.as_mut().unwrap();
                 //children[child_idx].remove_block(pos);
                 curr_node.is_full = false;
+                curr_node.material = 0;
 
                 remove_block_recursion(tree,pos,child_idx);
                 return;
@@ -189,41 +254,284 @@ impl Octree {
                 }
                 // its already not full so just remove the children and return
                 //curr_node.children_idx = None;
+                tree.free_children(node_idx);
                 curr_node.has_children = false;
                 return;
             }
         }
     }
     pub fn is_solid_at(&self,pos:IVec3) -> bool {
+        self.material_at(pos).is_some()
+    }
+    /// Returns the material of the voxel at `pos` or `None` if it's empty
+    pub fn material_at(&self,pos:IVec3) -> Option<u32> {
         let head = &self.nodes[ROOT_IDX];
         if !inside_bouds(head,pos) {
-            return false;
+            return None;
         }
         let mut curr = head;
         loop {
             if curr.is_full {
-                return true;
+                return Some(curr.material);
             }
             if !curr.has_children || curr.size == 1 {
-                return false;
+                return None;
             }
 
             let child_idx = pos_to_idx(curr,pos);
             curr = &self.nodes[child_idx as usize];
         }
     }
-    pub fn gen_skeleton_mesh(&self) -> Mesh<Vertex> {
+    /// Fills every voxel inside `shape` with `material`
+    pub fn union_shape(&mut self, shape: &Shape, material: u32) {
+        self.apply_csg(CsgOp::Union, &|pos,size| shape.coverage(pos,size,material));
+    }
+    /// Clears every voxel inside `shape`
+    pub fn subtract_shape(&mut self, shape: &Shape) {
+        self.apply_csg(CsgOp::Subtract, &|pos,size| shape.coverage(pos,size,0));
+    }
+    /// Clears every voxel outside of `shape`
+    pub fn intersect_shape(&mut self, shape: &Shape) {
+        self.apply_csg(CsgOp::Intersect, &|pos,size| shape.coverage(pos,size,0));
+    }
+    /// Copies the full voxels of `other` moved by `offset` keeping their materials
+    pub fn union_octree(&mut self, other: &Octree, offset: IVec3) {
+        self.apply_csg(CsgOp::Union, &|pos,size| other.coverage(pos - offset,size));
+    }
+    pub fn subtract_octree(&mut self, other: &Octree, offset: IVec3) {
+        self.apply_csg(CsgOp::Subtract, &|pos,size| other.coverage(pos - offset,size));
+    }
+    pub fn intersect_octree(&mut self, other: &Octree, offset: IVec3) {
+        self.apply_csg(CsgOp::Intersect, &|pos,size| other.coverage(pos - offset,size));
+    }
+
+    /// Walks the tree and only descends into nodes the volume partially covers, nodes that are
+    /// fully inside or outside are filled/cleared at once
+    fn apply_csg(&mut self, op: CsgOp, coverage: &dyn Fn(IVec3,u32) -> Coverage) {
+        self.generation += 1;
+        unsafe { csg_recursion(self,op,coverage,ROOT_IDX as i32) };
+
+        unsafe fn csg_recursion(tree: &mut Octree, op: CsgOp, coverage: &dyn Fn(IVec3,u32) -> Coverage, node_idx: i32) {
+            let curr_node: &mut OctreeNode = &mut *((&mut tree.nodes[node_idx as usize]) as *mut _);
+
+            match (op, coverage(curr_node.position,curr_node.size)) {
+                (CsgOp::Union, Coverage::Inside(material)) => {
+                    // drop the children and fill the whole node
+                    tree.free_children(node_idx);
+                    curr_node.has_children = false;
+                    curr_node.is_full = true;
+                    curr_node.material = material;
+                    return;
+                }
+                (CsgOp::Subtract, Coverage::Inside(_)) | (CsgOp::Intersect, Coverage::Outside) => {
+                    tree.free_children(node_idx);
+                    curr_node.has_children = false;
+                    curr_node.is_full = false;
+                    curr_node.material = 0;
+                    return;
+                }
+                (CsgOp::Union, Coverage::Outside) | 
+                (CsgOp::Subtract, Coverage::Outside) | 
+                (CsgOp::Intersect, Coverage::Inside(_)) => return,
+                (_, Coverage::Partial) => (),
+            }
+
+            // partially covered
+            assert!(curr_node.size != 1, "a single voxel can't be partially covered");
+            if !curr_node.has_children {
+                if !curr_node.is_full && op != CsgOp::Union {
+                    // is empty => nothing to remove
+                    return;
+                }
+                curr_node.has_children = true;
+                tree.devide_node(node_idx,curr_node.is_full,curr_node.material);
+                let curr_node: &mut OctreeNode = &mut *((&mut tree.nodes[node_idx as usize]) as *mut _);
+                curr_node.is_full = false;
+                curr_node.material = 0;
+            }
+            let children_idx = tree.nodes[node_idx as usize].children_idx;
+            for child_idx in children_idx {
+                csg_recursion(tree,op,coverage,child_idx);
+            }
+            tree.try_merge(node_idx);
+        }
+    }
+    /// Collapses the children of a node into a single leaf if they are all empty leaves or all
+    /// full of the same material
+    fn try_merge(&mut self, node_idx: i32) -> bool {
+        let node = &self.nodes[node_idx as usize];
+        if !node.has_children {
+            return false;
+        }
+        let first = &self.nodes[node.children_idx[0] as usize];
+        for child_idx in node.children_idx {
+            let child = &self.nodes[child_idx as usize];
+            if child.has_children || child.is_full != first.is_full || child.material != first.material {
+                return false;
+            }
+        }
+        let (is_full, material) = (first.is_full, first.material);
+
+        self.free_children(node_idx);
+        let node = &mut self.nodes[node_idx as usize];
+        node.has_children = false;
+        node.is_full = is_full;
+        node.material = material;
+        true
+    }
+    /// Classifies the cube at `pos` with the side `size` (in the trees coordinates) against the
+    /// content of the tree
+    fn coverage(&self, pos: IVec3, size: u32) -> Coverage {
+        let root = &self.nodes[ROOT_IDX];
+        let size = size as i32;
+        let root_min = root.position;
+        let root_max = root.position + root.size as i32;
+        let cube_max = pos + size;
+
+        // outside of the whole tree
+        if cube_max.x <= root_min.x || pos.x >= root_max.x ||
+           cube_max.y <= root_min.y || pos.y >= root_max.y ||
+           cube_max.z <= root_min.z || pos.z >= root_max.z {
+            return Coverage::Outside;
+        }
+        // sticks out of the tree
+        if pos.x < root_min.x || cube_max.x > root_max.x ||
+           pos.y < root_min.y || cube_max.y > root_max.y ||
+           pos.z < root_min.z || cube_max.z > root_max.z {
+            return Coverage::Partial;
+        }
+
+        let mut curr = root;
+        loop {
+            if !curr.has_children {
+                if curr.is_full {
+                    return Coverage::Inside(curr.material);
+                } else {
+                    return Coverage::Outside;
+                }
+            }
+            let child = &self.nodes[pos_to_idx(curr,pos) as usize];
+            let child_max = child.position + child.size as i32;
+            if cube_max.x > child_max.x || cube_max.y > child_max.y || cube_max.z > child_max.z {
+                // spans multiple children
+                return Coverage::Partial;
+            }
+            curr = child;
+        }
+    }
+    pub fn stats(&self) -> OctreeStats {
+        let mut stats = OctreeStats {
+            reachable_nodes: 0,
+            orphaned_nodes: 0,
+            max_depth: 0,
+            full_leaves: 0,
+            empty_leaves: 0,
+            mixed_nodes: 0,
+            mem_used: self.nodes.len() * std::mem::size_of::<OctreeNode>(),
+            mem_reserved: self.nodes.capacity() * std::mem::size_of::<OctreeNode>(),
+        };
+        stats_recursion(self,&mut stats,ROOT_IDX as i32,0);
+        stats.orphaned_nodes = self.nodes.len() - stats.reachable_nodes;
+        return stats;
+
+        fn stats_recursion(tree: &Octree, stats: &mut OctreeStats, node_idx: i32, depth: u32) {
+            let node = &tree.nodes[node_idx as usize];
+            stats.reachable_nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            if node.has_children {
+                stats.mixed_nodes += 1;
+                for child_idx in node.children_idx {
+                    stats_recursion(tree,stats,child_idx,depth + 1);
+                }
+            } else if node.is_full {
+                stats.full_leaves += 1;
+            } else {
+                stats.empty_leaves += 1;
+            }
+        }
+    }
+    /// Checks the structural invariants of the tree and returns the first violation found
+    pub fn validate(&self) -> Result<(),String> {
+        let root = &self.nodes[ROOT_IDX];
+        if !root.size.is_power_of_two() {
+            return Err(format!("root size {} isn't a power of two",root.size));
+        }
+        let mut visited = vec![false;self.nodes.len()];
+        return validate_recursion(self,&mut visited,ROOT_IDX as i32);
+
+        fn validate_recursion(tree: &Octree, visited: &mut Vec<bool>, node_idx: i32) -> Result<(),String> {
+            if node_idx < 0 || node_idx as usize >= tree.nodes.len() {
+                return Err(format!("node index {node_idx} out of range"));
+            }
+            if visited[node_idx as usize] {
+                return Err(format!("node {node_idx} is reachable more than once"));
+            }
+            visited[node_idx as usize] = true;
+
+            let node = &tree.nodes[node_idx as usize];
+            if !node.size.is_power_of_two() {
+                return Err(format!("node {node_idx} size {} isn't a power of two",node.size));
+            }
+            if !node.has_children {
+                return Ok(());
+            }
+            if node.is_full {
+                return Err(format!("node {node_idx} is full and has children"));
+            }
+            if node.size == 1 {
+                return Err(format!("node {node_idx} is a single voxel and has children"));
+            }
+
+            let node_max = node.position + node.size as i32;
+            for child_idx in node.children_idx {
+                if child_idx < 0 || child_idx as usize >= tree.nodes.len() {
+                    return Err(format!("node {node_idx} has child index {child_idx} out of range"));
+                }
+                let child = &tree.nodes[child_idx as usize];
+                let child_max = child.position + child.size as i32;
+                if child.size != node.size / 2 {
+                    return Err(format!("node {child_idx} has size {} but its parent {node_idx} has {}",child.size,node.size));
+                }
+                if child.position.x < node.position.x || child_max.x > node_max.x ||
+                   child.position.y < node.position.y || child_max.y > node_max.y ||
+                   child.position.z < node.position.z || child_max.z > node_max.z {
+                    return Err(format!("node {child_idx} is outside of its parent {node_idx}"));
+                }
+            }
+
+            let first = &tree.nodes[node.children_idx[0] as usize];
+            let mergeable = node.children_idx.iter().all(|idx| {
+                let child = &tree.nodes[*idx as usize];
+                !child.has_children && child.is_full == first.is_full && child.material == first.material
+            });
+            if mergeable {
+                return Err(format!("node {node_idx} has children that should be merged"));
+            }
+
+            for child_idx in node.children_idx {
+                validate_recursion(tree,visited,child_idx)?;
+            }
+            Ok(())
+        }
+    }
+    /// Outlines of the nodes, without the ones smaller than `min_size`.
+    /// The leaves of a whole chunk are more lines than are worth drawing
+    pub fn gen_skeleton_mesh(&self, min_size: u32) -> Mesh<Vertex> {
         let mut out = Mesh::new();
-        gen_skeleton_mesh_recursion(self,&mut out,ROOT_IDX as i32);
+        gen_skeleton_mesh_recursion(self,&mut out,ROOT_IDX as i32,min_size);
         return out;
 
-        fn gen_skeleton_mesh_recursion(tree: &Octree, mesh: &mut Mesh<Vertex>, node_idx: i32) {
+        fn gen_skeleton_mesh_recursion(tree: &Octree, mesh: &mut Mesh<Vertex>, node_idx: i32, min_size: u32) {
             let node = &tree.nodes[node_idx as usize];
+            if node.size < min_size {
+                return;
+            }
 
             if node.has_children {
                 let children_idx = node.children_idx;
                 for child_idx in children_idx {
-                    gen_skeleton_mesh_recursion(tree,mesh,child_idx);
+                    gen_skeleton_mesh_recursion(tree,mesh,child_idx,min_size);
                 }
             }
             mesh.join_with(&gen_cube_skeleton(node.size as i32,node.position));
@@ -306,3 +614,385 @@ pub fn pos_to_idx(node: &OctreeNode,pos:IVec3) -> i32 {
         }
     }
 }
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum CsgOp {
+    Union,
+    Subtract,
+    Intersect,
+}
+/// How much of a node a volume covers, `Inside` carries the material the node would be filled with
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Coverage {
+    Outside,
+    Inside(u32),
+    Partial,
+}
+#[derive(Clone,Copy,Debug)]
+pub enum Shape {
+    Sphere { center: Vec3, radius: f32 },
+    /// `min` inclusive, `max` exclusive
+    Box { min: IVec3, max: IVec3 },
+    /// Standing on the XZ plane, `base` is the center of the bottom cap
+    Cylinder { base: Vec3, radius: f32, height: f32 },
+}
+impl Shape {
+    /// Single voxels are tested at their center so they are never `Partial`
+    pub fn coverage(&self, pos: IVec3, size: u32, material: u32) -> Coverage {
+        if size == 1 {
+            let center = pos.as_vec3() + 0.5;
+            if self.contains(center) {
+                return Coverage::Inside(material);
+            } else {
+                return Coverage::Outside;
+            }
+        }
+        let min = pos.as_vec3();
+        let max = (pos + size as i32).as_vec3();
+
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let r_squared = radius * radius;
+                if dist_squared_to_nearest(center,min,max) > r_squared {
+                    Coverage::Outside
+                } else if dist_squared_to_farthest(center,min,max) <= r_squared {
+                    Coverage::Inside(material)
+                } else {
+                    Coverage::Partial
+                }
+            }
+            Shape::Box { min: box_min, max: box_max } => {
+                let cube_min = pos;
+                let cube_max = pos + size as i32;
+                if cube_max.x <= box_min.x || cube_min.x >= box_max.x ||
+                   cube_max.y <= box_min.y || cube_min.y >= box_max.y ||
+                   cube_max.z <= box_min.z || cube_min.z >= box_max.z {
+                    Coverage::Outside
+                } else if cube_min.x >= box_min.x && cube_max.x <= box_max.x &&
+                          cube_min.y >= box_min.y && cube_max.y <= box_max.y &&
+                          cube_min.z >= box_min.z && cube_max.z <= box_max.z {
+                    Coverage::Inside(material)
+                } else {
+                    Coverage::Partial
+                }
+            }
+            Shape::Cylinder { base, radius, height } => {
+                let r_squared = radius * radius;
+                // ignore the y axis
+                let base_xz = vec3!(base.x,0.,base.z);
+                let min_xz  = vec3!(min.x,0.,min.z);
+                let max_xz  = vec3!(max.x,0.,max.z);
+                if max.y <= base.y || min.y >= base.y + height ||
+                   dist_squared_to_nearest(base_xz,min_xz,max_xz) > r_squared {
+                    Coverage::Outside
+                } else if min.y >= base.y && max.y <= base.y + height &&
+                          dist_squared_to_farthest(base_xz,min_xz,max_xz) <= r_squared {
+                    Coverage::Inside(material)
+                } else {
+                    Coverage::Partial
+                }
+            }
+        }
+    }
+    pub fn contains(&self, point: Vec3) -> bool {
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let d = point - center;
+                d.x*d.x + d.y*d.y + d.z*d.z <= radius * radius
+            }
+            Shape::Box { min, max } => {
+                point.x >= min.x as f32 && point.x < max.x as f32 &&
+                point.y >= min.y as f32 && point.y < max.y as f32 &&
+                point.z >= min.z as f32 && point.z < max.z as f32
+            }
+            Shape::Cylinder { base, radius, height } => {
+                let dx = point.x - base.x;
+                let dz = point.z - base.z;
+                point.y >= base.y && point.y <= base.y + height && dx*dx + dz*dz <= radius * radius
+            }
+        }
+    }
+}
+fn dist_squared_to_nearest(point: Vec3, min: Vec3, max: Vec3) -> f32 {
+    let dx = (min.x - point.x).max(0.).max(point.x - max.x);
+    let dy = (min.y - point.y).max(0.).max(point.y - max.y);
+    let dz = (min.z - point.z).max(0.).max(point.z - max.z);
+    dx*dx + dy*dy + dz*dz
+}
+fn dist_squared_to_farthest(point: Vec3, min: Vec3, max: Vec3) -> f32 {
+    let dx = (point.x - min.x).abs().max((point.x - max.x).abs());
+    let dy = (point.y - min.y).abs().max((point.y - max.y).abs());
+    let dz = (point.z - min.z).abs().max((point.z - max.z).abs());
+    dx*dx + dy*dy + dz*dz
+}
+
+/// 8 byte node of `PackedOctree`, the children of a node are always stored as a contiguous group
+/// of 8 starting at `child_ptr` in the same order as `OctreeNode::children_idx`.
+/// For a full leaf slot `child_ptr` holds the material instead, empty slots are left zeroed.
+/// The size and position of a node aren't stored, they are derived during traversal
+#[repr(C)]
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct PackedNode {
+    pub child_ptr: u32,
+    /// bit i set => child i isn't empty
+    pub valid_mask: u8,
+    /// bit i set => child i is a full leaf
+    pub leaf_mask: u8,
+    pub _padding: [u8;2],
+}
+impl PackedNode {
+    pub fn is_valid(&self, child: usize) -> bool {
+        self.valid_mask & (1 << child) != 0
+    }
+    pub fn is_leaf(&self, child: usize) -> bool {
+        self.leaf_mask & (1 << child) != 0
+    }
+}
+/// Compact layout of an `Octree` for the gpu, `nodes[0]` is the root and is always an inner node
+pub struct PackedOctree {
+    pub nodes: Vec<PackedNode>,
+    pub size: u32,
+    pub position: IVec3,
+}
+impl PackedOctree {
+    /// Only encodes nodes reachable from the root so orphans are dropped
+    pub fn from_octree(octree: &Octree) -> Self {
+        let root = &octree.nodes[ROOT_IDX];
+        assert!(root.size > 1, "can't pack a tree of a single voxel");
+
+        let mut out = PackedOctree {
+            nodes: vec![PackedNode::default()],
+            size: root.size,
+            position: root.position,
+        };
+
+        if !root.has_children {
+            // a uniform root is stored as 8 identical leaves
+            if root.is_full {
+                out.nodes[0] = PackedNode { child_ptr: 1, valid_mask: 0xFF, leaf_mask: 0xFF, _padding: [0;2] };
+                out.nodes.extend_from_slice(&[PackedNode { child_ptr: root.material, ..Default::default() };8]);
+            }
+            return out;
+        }
+
+        // (index in octree.nodes, index in out.nodes)
+        let mut queue = std::collections::VecDeque::new();
+        queue.push_back((ROOT_IDX as i32, 0));
+
+        while let Some((node_idx,packed_idx)) = queue.pop_front() {
+            let node = &octree.nodes[node_idx as usize];
+            let group_start = out.nodes.len();
+            out.nodes.extend_from_slice(&[PackedNode::default();8]);
+
+            let mut packed = PackedNode { child_ptr: group_start as u32, ..Default::default() };
+            for (i,child_idx) in node.children_idx.iter().enumerate() {
+                let child = &octree.nodes[*child_idx as usize];
+                if child.has_children {
+                    packed.valid_mask |= 1 << i;
+                    queue.push_back((*child_idx,group_start + i));
+                } else if child.is_full {
+                    packed.valid_mask |= 1 << i;
+                    packed.leaf_mask |= 1 << i;
+                    out.nodes[group_start + i].child_ptr = child.material;
+                }
+            }
+            out.nodes[packed_idx] = packed;
+        }
+        out
+    }
+    pub fn mem_size(&self) -> usize {
+        std::mem::size_of::<PackedNode>() * self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    /// Checks every voxel of the tree against `expected`
+    fn assert_voxels(tree: &Octree, expected: impl Fn(IVec3) -> Option<u32>) {
+        for x in 0..SIZE as i32 {
+            for y in 0..SIZE as i32 {
+                for z in 0..SIZE as i32 {
+                    let pos = ivec3!(x,y,z);
+                    assert_eq!(tree.material_at(pos), expected(pos), "voxel {:?}", pos);
+                }
+            }
+        }
+    }
+    fn center(pos: IVec3) -> Vec3 {
+        pos.as_vec3() + 0.5
+    }
+
+    #[test]
+    fn union_sphere() {
+        let mut tree = Octree::new(SIZE,ivec3!(0,0,0));
+        let sphere = Shape::Sphere { center: vec3!(8.,8.,8.), radius: 5. };
+        tree.union_shape(&sphere,3);
+
+        assert_eq!(tree.validate(), Ok(()));
+        assert_voxels(&tree, |pos| sphere.contains(center(pos)).then_some(3));
+        assert_eq!(tree.coverage(ivec3!(8,8,8),2), Coverage::Inside(3));
+        assert_eq!(tree.coverage(ivec3!(0,0,0),2), Coverage::Outside);
+        assert_eq!(tree.coverage(ivec3!(0,0,0),SIZE), Coverage::Partial);
+    }
+    #[test]
+    fn subtract_box() {
+        let mut tree = Octree::new_full(SIZE,ivec3!(0,0,0),1);
+        let cut = Shape::Box { min: ivec3!(4,4,4), max: ivec3!(12,12,12) };
+        tree.subtract_shape(&cut);
+
+        assert_eq!(tree.validate(), Ok(()));
+        assert_voxels(&tree, |pos| (!cut.contains(center(pos))).then_some(1));
+        assert_eq!(tree.coverage(ivec3!(4,4,4),4), Coverage::Outside);
+        assert_eq!(tree.coverage(ivec3!(0,0,0),4), Coverage::Inside(1));
+        assert_eq!(tree.coverage(ivec3!(0,0,0),8), Coverage::Partial);
+    }
+    #[test]
+    fn intersect_cylinder() {
+        let mut tree = Octree::new_full(SIZE,ivec3!(0,0,0),2);
+        let cylinder = Shape::Cylinder { base: vec3!(8.,2.,8.), radius: 4., height: 10. };
+        tree.intersect_shape(&cylinder);
+
+        assert_eq!(tree.validate(), Ok(()));
+        assert_voxels(&tree, |pos| cylinder.contains(center(pos)).then_some(2));
+    }
+    #[test]
+    fn union_octree_keeps_materials() {
+        let mut other = Octree::new(SIZE,ivec3!(0,0,0));
+        other.add_block(ivec3!(1,1,1),5);
+        other.add_block(ivec3!(2,1,1),6);
+
+        let mut tree = Octree::new(SIZE,ivec3!(0,0,0));
+        tree.union_octree(&other,ivec3!(4,0,0));
+
+        assert_eq!(tree.validate(), Ok(()));
+        assert_voxels(&tree, |pos| match (pos.x,pos.y,pos.z) {
+            (5,1,1) => Some(5),
+            (6,1,1) => Some(6),
+            _ => None,
+        });
+    }
+    #[test]
+    fn subtract_everything_merges_to_root() {
+        let mut tree = Octree::new(SIZE,ivec3!(0,0,0));
+        tree.union_shape(&Shape::Sphere { center: vec3!(8.,8.,8.), radius: 6. },1);
+        tree.subtract_shape(&Shape::Box { min: ivec3!(0,0,0), max: ivec3!(16,16,16) });
+
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.coverage(ivec3!(0,0,0),SIZE), Coverage::Outside);
+        assert!(!tree.nodes[ROOT_IDX].has_children);
+    }
+    #[test]
+    fn repeated_csg_reuses_nodes() {
+        let mut tree = Octree::new(SIZE,ivec3!(0,0,0));
+        let sphere = Shape::Sphere { center: vec3!(7.,9.,8.), radius: 5.5 };
+        let cut = Shape::Box { min: ivec3!(3,3,3), max: ivec3!(9,9,9) };
+
+        // the first pass starts from an empty tree so it may allocate less than the later ones
+        for _ in 0..2 {
+            tree.union_shape(&sphere,1);
+            tree.subtract_shape(&cut);
+        }
+        let len = tree.nodes.len();
+        for _ in 0..10 {
+            tree.union_shape(&sphere,1);
+            tree.subtract_shape(&cut);
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.nodes.len(), len);
+    }
+    #[test]
+    fn add_remove_block_reuses_nodes() {
+        let mut tree = Octree::new(SIZE,ivec3!(0,0,0));
+        tree.add_block(ivec3!(3,4,5),1);
+        tree.remove_block(ivec3!(3,4,5));
+        let len = tree.nodes.len();
+        for _ in 0..10 {
+            tree.add_block(ivec3!(3,4,5),1);
+            tree.remove_block(ivec3!(3,4,5));
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.nodes.len(), len);
+        assert_eq!(tree.material_at(ivec3!(3,4,5)), None);
+    }
+    #[test]
+    fn stats_single_block() {
+        let mut tree = Octree::new(8,ivec3!(0,0,0));
+        tree.add_block(ivec3!(1,2,3),1);
+        let stats = tree.stats();
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.mixed_nodes, 3);
+        assert_eq!(stats.full_leaves, 1);
+        assert_eq!(stats.empty_leaves, 7 * 3);
+        assert_eq!(stats.reachable_nodes, 1 + 8 * 3);
+        assert_eq!(stats.orphaned_nodes, 0);
+        assert_eq!(stats.mem_used, tree.nodes.len() * std::mem::size_of::<OctreeNode>());
+
+        tree.remove_block(ivec3!(1,2,3));
+        let stats = tree.stats();
+        assert_eq!(stats.max_depth, 0);
+        assert_eq!(stats.reachable_nodes, 1);
+        assert_eq!(stats.empty_leaves, 1);
+        assert_eq!(stats.orphaned_nodes, 8 * 3);
+    }
+    #[test]
+    fn stats_full_root() {
+        let stats = Octree::new_full(8,ivec3!(0,0,0),1).stats();
+        assert_eq!(stats.reachable_nodes, 1);
+        assert_eq!(stats.full_leaves, 1);
+        assert_eq!(stats.mixed_nodes, 0);
+    }
+    /// A tree with a single voxel at the origin, its root and the first child have children
+    fn single_block_tree() -> Octree {
+        let mut tree = Octree::new(4,ivec3!(0,0,0));
+        tree.add_block(ivec3!(0,0,0),1);
+        assert_eq!(tree.validate(), Ok(()));
+        tree
+    }
+    #[test]
+    fn validate_full_node_with_children() {
+        let mut tree = single_block_tree();
+        tree.nodes[ROOT_IDX].is_full = true;
+        assert!(tree.validate().unwrap_err().contains("is full and has children"));
+    }
+    #[test]
+    fn validate_mergeable_children() {
+        let mut tree = single_block_tree();
+        let first = tree.nodes[ROOT_IDX].children_idx[0] as usize;
+        tree.nodes[first].has_children = false;
+        assert!(tree.validate().unwrap_err().contains("should be merged"));
+    }
+    #[test]
+    fn validate_child_outside_parent() {
+        let mut tree = single_block_tree();
+        let last = tree.nodes[ROOT_IDX].children_idx[7] as usize;
+        tree.nodes[last].position = ivec3!(4,0,0);
+        assert!(tree.validate().unwrap_err().contains("outside of its parent"));
+    }
+    #[test]
+    fn validate_shared_child() {
+        let mut tree = single_block_tree();
+        tree.nodes[ROOT_IDX].children_idx[1] = tree.nodes[ROOT_IDX].children_idx[0];
+        assert!(tree.validate().unwrap_err().contains("more than once"));
+    }
+    #[test]
+    fn validate_bad_sizes() {
+        let mut tree = single_block_tree();
+        let last = tree.nodes[ROOT_IDX].children_idx[7] as usize;
+        tree.nodes[last].size = 1;
+        assert!(tree.validate().unwrap_err().contains("has size 1"));
+
+        let mut tree = single_block_tree();
+        tree.nodes[ROOT_IDX].size = 6;
+        assert!(tree.validate().unwrap_err().contains("isn't a power of two"));
+    }
+    #[test]
+    fn validate_index_out_of_range() {
+        let mut tree = single_block_tree();
+        tree.nodes[ROOT_IDX].children_idx[3] = 100;
+        assert!(tree.validate().unwrap_err().contains("out of range"));
+    }
+}
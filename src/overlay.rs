@@ -0,0 +1,190 @@
+use my_math::prelude::*;
+use std::hash::{DefaultHasher,Hash,Hasher};
+use std::mem::size_of;
+
+use crate::camera::Camera;
+use crate::chunk::{self,Chunk,BRICK_SIZE};
+use crate::mesh::{self,Mesh};
+use crate::octree::Octree;
+use crate::render::{self,RenderTargets};
+use crate::shader::{compile_shader,ShaderProgram};
+use crate::vertex::{Vertex,VertexAttributes};
+
+pub const OCTREE_LINE: Vec3 = Vec3 { x: 0.2, y: 0.8, z: 1. };
+/// Only the bricks this close to the camera get outlined, a chunk has thousands of them
+pub const BRICK_LINE_RADIUS: f32 = 128.;
+/// The brick lines follow the camera in steps of this many voxels
+const BRICK_LINE_STEP: f32 = 32.;
+/// Octree nodes smaller than this aren't outlined
+pub const OCTREE_MIN_NODE: u32 = BRICK_SIZE as u32;
+
+/// Rasterized lines over the traced image: chunk bounds, allocated brick bounds and the nodes of
+/// an octree. They are depth tested against the depth image of the trace passes
+pub struct Overlay {
+    program: ShaderProgram,
+    vao: u32,
+    vbo: u32,
+    ebo: u32,
+    /// Indices in the element buffer
+    len: u32,
+    /// What the buffers were built from, see `lines_key`
+    key: Option<u64>,
+}
+impl Overlay {
+    pub unsafe fn new() -> Self {
+        let vert = compile_shader(gl::VERTEX_SHADER,"./shaders/3d_perspective.vert");
+        let frag = compile_shader(gl::FRAGMENT_SHADER,"./shaders/overlay.frag");
+        let program = ShaderProgram::create_program(vert,frag);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+
+        let (mut vao,mut vbo,mut ebo) = (0,0,0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::GenBuffers(1, &mut ebo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+        Vertex::set_attribs();
+        Overlay { program, vao, vbo, ebo, len: 0, key: None }
+    }
+    /// Rebuilds the lines when anything they show changed. `bounds` shows the chunks and bricks
+    pub unsafe fn update(&mut self, chunks: &[&Chunk], camera: &Camera, bounds: bool, octree: Option<&Octree>) {
+        let anchor = brick_line_anchor(camera.pos);
+        let key = lines_key(chunks,anchor,bounds,octree);
+        if self.key == Some(key) {
+            return;
+        }
+        self.key = Some(key);
+
+        let mesh = build_lines(chunks,anchor,bounds,octree);
+        gl::BindVertexArray(self.vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+        gl::BufferData(gl::ARRAY_BUFFER,
+            (mesh.verts.len() * size_of::<Vertex>()) as isize,
+            mesh.verts.as_ptr() as *const _,
+            gl::DYNAMIC_DRAW,
+        );
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+        gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+            (mesh.indices.len() * size_of::<u32>()) as isize,
+            mesh.indices.as_ptr() as *const _,
+            gl::DYNAMIC_DRAW,
+        );
+        self.len = mesh.indices.len() as u32;
+    }
+    /// Draws the lines over what `Renderer::present` drew to the `framebuffer` sized window
+    pub unsafe fn draw(&self, targets: &RenderTargets, camera: &Camera, framebuffer: (u32,u32)) {
+        if self.len == 0 {
+            return;
+        }
+        gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, targets.depth);
+
+        let program = self.program;
+        gl::UseProgram(*program);
+        let transform = render::view_projection(camera,targets.width,targets.height);
+        program.set_mat4("transform",transform.as_ptr());
+        program.set_int("trace_depth",0);
+        program.set_vec2("viewport",Vec2::new(framebuffer.0 as f32,framebuffer.1 as f32));
+        program.set_float("fov",camera.fov);
+        program.set_float("aspect",targets.width as f32 / targets.height as f32);
+
+        gl::BindVertexArray(self.vao);
+        gl::DrawElements(gl::LINES, self.len as i32, gl::UNSIGNED_INT, std::ptr::null());
+    }
+    pub unsafe fn delete(&self) {
+        gl::DeleteVertexArrays(1, &self.vao);
+        gl::DeleteBuffers(1, &self.vbo);
+        gl::DeleteBuffers(1, &self.ebo);
+        gl::DeleteProgram(*self.program);
+    }
+}
+
+/// Center of the sphere the brick lines are drawn in, moves in whole `BRICK_LINE_STEP`s
+fn brick_line_anchor(pos: Vec3) -> Vec3 {
+    let snap = |x: f32| ((x / BRICK_LINE_STEP).floor() + 0.5) * BRICK_LINE_STEP;
+    vec3!(snap(pos.x),snap(pos.y),snap(pos.z))
+}
+
+/// Changes whenever `build_lines` would build different lines. Bricks stay allocated once they
+/// are, so the positions of the chunks and their brick counts cover every edit
+fn lines_key(chunks: &[&Chunk], anchor: Vec3, bounds: bool, octree: Option<&Octree>) -> u64 {
+    let mut chunks_key: u64 = 0;
+    if bounds {
+        for chunk in chunks {
+            let mut hasher = DefaultHasher::new();
+            (chunk.pos,chunk.brickmap.data.len()).hash(&mut hasher);
+            // The chunks are sorted by their distance to the camera, the sum ignores the order
+            chunks_key = chunks_key.wrapping_add(hasher.finish());
+        }
+    }
+    let mut hasher = DefaultHasher::new();
+    chunks_key.hash(&mut hasher);
+    [anchor.x,anchor.y,anchor.z].map(f32::to_bits).hash(&mut hasher);
+    bounds.hash(&mut hasher);
+    octree.map(Octree::generation).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn build_lines(chunks: &[&Chunk], anchor: Vec3, bounds: bool, octree: Option<&Octree>) -> Mesh<Vertex> {
+    let mut mesh = Mesh::new();
+    if bounds {
+        for chunk in chunks {
+            let origin = chunk.pos * chunk::SIZE as i32;
+            let grid = &chunk.brickmap.grid;
+            // Grid cells that can be within the radius
+            let min = (anchor - BRICK_LINE_RADIUS - origin.as_vec3()) / BRICK_SIZE as f32;
+            let max = (anchor + BRICK_LINE_RADIUS - origin.as_vec3()) / BRICK_SIZE as f32;
+            for x in (min.x.floor() as i32).max(0)..(max.x.ceil() as i32).min(grid.size.x) {
+                for y in (min.y.floor() as i32).max(0)..(max.y.ceil() as i32).min(grid.size.y) {
+                    for z in (min.z.floor() as i32).max(0)..(max.z.ceil() as i32).min(grid.size.z) {
+                        let cell = ivec3!(x,y,z);
+                        let pos = origin + cell * BRICK_SIZE as i32;
+                        let center = pos.as_vec3() + BRICK_SIZE as f32 * 0.5;
+                        if grid.get(cell) == u32::MAX || (center - anchor).mag() > BRICK_LINE_RADIUS {
+                            continue;
+                        }
+                        mesh.join_with(&colored(mesh::gen_cube_skeleton(BRICK_SIZE as i32,pos),render::BRICK_LINE));
+                    }
+                }
+            }
+            // After the bricks so the chunk edges are drawn over theirs
+            mesh.join_with(&colored(mesh::gen_cube_skeleton(chunk::SIZE as i32,origin),render::CHUNK_LINE));
+        }
+    }
+    if let Some(octree) = octree {
+        mesh.join_with(&colored(octree.gen_skeleton_mesh(OCTREE_MIN_NODE),OCTREE_LINE));
+    }
+    mesh
+}
+
+fn colored(mut mesh: Mesh<Vertex>, color: Vec3) -> Mesh<Vertex> {
+    for vert in mesh.verts.iter_mut() {
+        vert.col = color;
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::Shape;
+
+    #[test]
+    fn lines_key_follows_octree_edits() {
+        let mut tree = Octree::new(16,ivec3!(0,0,0));
+        let sphere = Shape::Sphere { center: vec3!(7.,9.,8.), radius: 5.5 };
+        tree.union_shape(&sphere,1);
+        tree.subtract_shape(&sphere);
+        let len = tree.nodes.len();
+        let key = lines_key(&[],Vec3::ZERO,false,Some(&tree));
+
+        // the merges freed every node so the union reuses them
+        tree.union_shape(&sphere,1);
+        assert_eq!(tree.nodes.len(), len);
+        assert_ne!(lines_key(&[],Vec3::ZERO,false,Some(&tree)), key);
+        assert_ne!(lines_key(&[],Vec3::ZERO,false,None), key);
+    }
+}
@@ -0,0 +1,404 @@
+use my_math::prelude::*;
+
+use crate::cpu_render::Image;
+
+/// Rec. 709 luma weights, the same in the post shaders
+pub const LUMA: Vec3 = Vec3 { x: 0.2126, y: 0.7152, z: 0.0722 };
+/// One side of the 9 tap gaussian in `post_bloom.comp`, the center first
+pub const BLUR_WEIGHTS: [f32;5] = [0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+/// Edge length of `Lut::default_grade`
+pub const DEFAULT_LUT_SIZE: u32 = 16;
+
+/// The post-processing chain after the trace passes, every effect runs in the order of the fields.
+/// All of them work on the linear HDR color before the tone mapping
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct PostSettings {
+    pub bloom: bool,
+    /// Luma above which pixels start to glow
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    /// Blur passes at half resolution, every one makes the glow wider
+    pub bloom_passes: u32,
+    /// Color grading with the renderer's `Lut`
+    pub grading: bool,
+    pub vignette: bool,
+    /// How much darker the corners get, 0 to 1
+    pub vignette_strength: f32,
+    pub sharpen: bool,
+    pub sharpen_strength: f32,
+}
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            bloom: true,
+            bloom_threshold: 1.,
+            bloom_intensity: 0.3,
+            bloom_passes: 3,
+            grading: true,
+            vignette: true,
+            vignette_strength: 0.3,
+            sharpen: false,
+            sharpen_strength: 0.2,
+        }
+    }
+}
+
+/// Mode uniform of `post_bloom.comp`
+#[repr(i32)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum BloomMode {
+    Bright      = 0,
+    Blur        = 1,
+    Composite   = 2,
+}
+
+/// 3D color lookup table over 0 to 1, red changes fastest like in `.cube` files.
+/// `grade` looks the HDR color up through `c / (1 + c)`, so 0.5 in the table is a color of 1
+#[derive(Debug,Clone)]
+pub struct Lut {
+    pub size: u32,
+    pub data: Vec<[f32;3]>,
+}
+impl Lut {
+    pub fn from_fn(size: u32, f: impl Fn(Vec3) -> Vec3) -> Self {
+        assert!(size >= 2, "a lut needs at least 2 entries per axis");
+        let scale = 1. / (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let color = f(vec3!(r as f32 * scale, g as f32 * scale, b as f32 * scale));
+                    data.push([color.x,color.y,color.z]);
+                }
+            }
+        }
+        Lut { size, data }
+    }
+    /// Leaves the colors as they are
+    pub fn identity(size: u32) -> Self {
+        Self::from_fn(size, |c| c)
+    }
+    /// A bit more saturation and a slightly warm tint
+    pub fn default_grade() -> Self {
+        Self::from_fn(DEFAULT_LUT_SIZE, |c| {
+            let luma = c.dot(LUMA);
+            let gray = vec3!(luma,luma,luma);
+            let saturated = gray + (c - gray) * 1.15;
+            let warm = saturated * vec3!(1.03,1.,0.96);
+            vec3!(warm.x.clamp(0.,1.), warm.y.clamp(0.,1.), warm.z.clamp(0.,1.))
+        })
+    }
+    /// Reads the 3D part of an Adobe `.cube` file, the domain has to be 0 to 1
+    pub fn parse_cube(text: &str) -> Result<Self,String> {
+        let mut size = None;
+        let mut data = Vec::new();
+        for (i,line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap_or_default();
+            match first {
+                "LUT_3D_SIZE" => {
+                    let value = words.next().ok_or(format!("line {}: missing size",i + 1))?;
+                    size = Some(value.parse::<u32>().map_err(|err| format!("line {}: {err}",i + 1))?);
+                }
+                "TITLE" | "DOMAIN_MIN" | "DOMAIN_MAX" => (),
+                "LUT_1D_SIZE" => return Err("1D luts aren't supported".to_string()),
+                _ => {
+                    let mut color = [0.;3];
+                    for (j,word) in std::iter::once(first).chain(words).enumerate() {
+                        let channel = color.get_mut(j).ok_or(format!("line {}: more than 3 values",i + 1))?;
+                        *channel = word.parse().map_err(|err| format!("line {}: {err}",i + 1))?;
+                    }
+                    data.push(color);
+                }
+            }
+        }
+        let size = size.ok_or("missing LUT_3D_SIZE")?;
+        if size < 2 || data.len() != (size * size * size) as usize {
+            return Err(format!("expected {} entries, found {}",size * size * size,data.len()));
+        }
+        Ok(Lut { size, data })
+    }
+    fn at(&self, r: u32, g: u32, b: u32) -> Vec3 {
+        let [x,y,z] = self.data[(r + g * self.size + b * self.size * self.size) as usize];
+        vec3!(x,y,z)
+    }
+    /// Trilinear lookup like the `sampler3D` in `post_grade.comp`
+    pub fn sample(&self, color: Vec3) -> Vec3 {
+        let max = (self.size - 1) as f32;
+        let axis = |c: f32| {
+            let pos = c.clamp(0.,1.) * max;
+            let i = (pos.floor() as u32).min(self.size - 2);
+            (i, pos - i as f32)
+        };
+        let ((r,fr),(g,fg),(b,fb)) = (axis(color.x),axis(color.y),axis(color.z));
+        let lerp = |a: Vec3, b: Vec3, t: f32| a * (1. - t) + b * t;
+        let plane = |b: u32| lerp(
+            lerp(self.at(r,g,b),     self.at(r + 1,g,b),     fr),
+            lerp(self.at(r,g + 1,b), self.at(r + 1,g + 1,b), fr),
+            fg,
+        );
+        lerp(plane(b), plane(b + 1), fb)
+    }
+}
+
+/// The `.cube` lut at `path`, or `Lut::default_grade` when there is no file
+pub fn load_lut(path: &std::path::Path) -> Result<Lut,String> {
+    if !path.exists() {
+        return Ok(Lut::default_grade());
+    }
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {err}",path.display()))?;
+    Lut::parse_cube(&text).map_err(|err| format!("{}: {err}",path.display()))
+}
+
+/// Size of the bloom textures for a screen
+pub fn bloom_size(width: u32, height: u32) -> (u32,u32) {
+    ((width / 2).max(1), (height / 2).max(1))
+}
+
+/// Runs the enabled effects like `render::Renderer` does after the trace passes
+pub fn apply(image: &Image, settings: &PostSettings, lut: &Lut) -> Image {
+    let mut image = image.clone();
+    if settings.bloom {
+        image = bloom(&image, settings.bloom_threshold, settings.bloom_intensity, settings.bloom_passes);
+    }
+    if settings.grading {
+        image = grade(&image, lut);
+    }
+    if settings.vignette {
+        image = vignette(&image, settings.vignette_strength);
+    }
+    if settings.sharpen {
+        image = sharpen(&image, settings.sharpen_strength);
+    }
+    image
+}
+
+/// Same as `post_bloom.comp` with all of its modes
+pub fn bloom(image: &Image, threshold: f32, intensity: f32, passes: u32) -> Image {
+    let (width,height) = bloom_size(image.width,image.height);
+    let mut bright = Image::new(width,height);
+    for y in 0..height {
+        for x in 0..width {
+            let [r,g,b,_] = sample_bilinear(image, (x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+            let color = vec3!(r,g,b);
+            let luma = color.dot(LUMA);
+            let color = color * ((luma - threshold).max(0.) / luma.max(0.0001));
+            bright.set(x,y,[color.x,color.y,color.z,1.]);
+        }
+    }
+    for _ in 0..passes {
+        bright = blur(&bright, (1,0));
+        bright = blur(&bright, (0,1));
+    }
+
+    let mut out = image.clone();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let glow = sample_bilinear(&bright, (x as f32 + 0.5) / image.width as f32, (y as f32 + 0.5) / image.height as f32);
+            let mut pixel = image.get(x,y);
+            for i in 0..3 {
+                pixel[i] += glow[i] * intensity;
+            }
+            out.set(x,y,pixel);
+        }
+    }
+    out
+}
+
+/// One direction of the bloom blur, the edges are clamped
+pub fn blur(image: &Image, axis: (i32,i32)) -> Image {
+    let mut out = Image::new(image.width,image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let mut sum = [0.;3];
+            for (i,weight) in BLUR_WEIGHTS.iter().enumerate() {
+                let offsets: &[i32] = if i == 0 { &[0] } else { &[i as i32, -(i as i32)] };
+                for offset in offsets {
+                    let pixel = clamped(image, x as i32 + axis.0 * offset, y as i32 + axis.1 * offset);
+                    for c in 0..3 {
+                        sum[c] += pixel[c] * weight;
+                    }
+                }
+            }
+            out.set(x,y,[sum[0],sum[1],sum[2],1.]);
+        }
+    }
+    out
+}
+
+/// Same as `post_grade.comp`
+pub fn grade(image: &Image, lut: &Lut) -> Image {
+    let mut out = image.clone();
+    for pixel in out.pixels.iter_mut() {
+        let shape = |c: f32| c.max(0.) / (1. + c.max(0.));
+        let graded = lut.sample(vec3!(shape(pixel[0]),shape(pixel[1]),shape(pixel[2])));
+        let unshape = |c: f32| { let c = c.min(0.999); c / (1. - c) };
+        pixel[0] = unshape(graded.x);
+        pixel[1] = unshape(graded.y);
+        pixel[2] = unshape(graded.z);
+    }
+    out
+}
+
+/// Same as `post_vignette.comp`
+pub fn vignette(image: &Image, strength: f32) -> Image {
+    let mut out = image.clone();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let u = (x as f32 + 0.5) / image.width as f32 * 2. - 1.;
+            let v = (y as f32 + 0.5) / image.height as f32 * 2. - 1.;
+            let dist_squared = (u * u + v * v) / 2.;
+            let mut pixel = image.get(x,y);
+            for c in pixel[..3].iter_mut() {
+                *c *= 1. - strength * dist_squared;
+            }
+            out.set(x,y,pixel);
+        }
+    }
+    out
+}
+
+/// Same as `post_sharpen.comp`
+pub fn sharpen(image: &Image, strength: f32) -> Image {
+    let mut out = image.clone();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (xi,yi) = (x as i32, y as i32);
+            let center = image.get(x,y);
+            let neighbours = [(1,0),(-1,0),(0,1),(0,-1)].map(|(dx,dy)| clamped(image, xi + dx, yi + dy));
+            let mut pixel = center;
+            for c in 0..3 {
+                let sum: f32 = neighbours.iter().map(|n| n[c]).sum();
+                pixel[c] = (center[c] + strength * (center[c] * 4. - sum)).max(0.);
+            }
+            out.set(x,y,pixel);
+        }
+    }
+    out
+}
+
+/// Pixel with the coordinates clamped to the image, like `texelFetch` on clamped coordinates
+fn clamped(image: &Image, x: i32, y: i32) -> [f32;4] {
+    image.get(x.clamp(0, image.width as i32 - 1) as u32, y.clamp(0, image.height as i32 - 1) as u32)
+}
+
+/// Bilinear filtering with clamp to edge, the way the gpu samples at `u`,`v`
+pub fn sample_bilinear(image: &Image, u: f32, v: f32) -> [f32;4] {
+    let x = u * image.width as f32 - 0.5;
+    let y = v * image.height as f32 - 0.5;
+    let (x0,y0) = (x.floor(), y.floor());
+    let (fx,fy) = (x - x0, y - y0);
+    let (x0,y0) = (x0 as i32, y0 as i32);
+
+    let mut out = [0.;4];
+    let corners = [(0,0,(1. - fx) * (1. - fy)), (1,0,fx * (1. - fy)), (0,1,(1. - fx) * fy), (1,1,fx * fy)];
+    for (dx,dy,weight) in corners {
+        let pixel = clamped(image, x0 + dx, y0 + dy);
+        for i in 0..4 {
+            out[i] += pixel[i] * weight;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small image with a different color in every pixel
+    fn gradient(width: u32, height: u32, max: f32) -> Image {
+        let mut image = Image::new(width,height);
+        for y in 0..height {
+            for x in 0..width {
+                let u = x as f32 / (width - 1) as f32;
+                let v = y as f32 / (height - 1) as f32;
+                image.set(x,y,[u * max, v * max, (1. - u) * max, 1.]);
+            }
+        }
+        image
+    }
+    fn flat(width: u32, height: u32, pixel: [f32;4]) -> Image {
+        let mut image = Image::new(width,height);
+        image.pixels.fill(pixel);
+        image
+    }
+    fn cube(size: u32, entries: usize) -> String {
+        let mut text = format!("TITLE \"test\"\n# comment\nLUT_3D_SIZE {size}\n");
+        for i in 0..entries {
+            text += &format!("{} 0.5 1\n",i as f32 / entries as f32);
+        }
+        text
+    }
+
+    #[test]
+    fn identity_lut_keeps_the_image() {
+        let image = gradient(5,4,4.);
+        let graded = grade(&image,&Lut::identity(DEFAULT_LUT_SIZE));
+        for (a,b) in image.pixels.iter().zip(&graded.pixels) {
+            for c in 0..3 {
+                assert!((a[c] - b[c]).abs() <= 1e-4 * (1. + a[c]), "{a:?} != {b:?}");
+            }
+        }
+    }
+    #[test]
+    fn parse_cube_valid() {
+        let lut = Lut::parse_cube(&cube(2,8)).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.data[1], [0.125,0.5,1.]);
+    }
+    #[test]
+    fn parse_cube_rejects_bad_files() {
+        assert!(Lut::parse_cube(&cube(2,7)).is_err(), "too few entries");
+        assert!(Lut::parse_cube(&cube(2,9)).is_err(), "too many entries");
+        assert!(Lut::parse_cube(&cube(1,1)).is_err(), "size below 2");
+        assert!(Lut::parse_cube(&cube(2,8).replace("LUT_3D_SIZE 2\n","")).is_err(), "missing size");
+        assert!(Lut::parse_cube(&cube(2,8).replace("LUT_3D_SIZE 2","LUT_3D_SIZE two")).is_err(), "bad size");
+        assert!(Lut::parse_cube(&cube(2,8).replace("LUT_3D_SIZE 2","LUT_1D_SIZE 2")).is_err(), "1D lut");
+        assert!(Lut::parse_cube(&cube(2,8).replace("0.5 1","0.5 x")).is_err(), "bad value");
+        assert!(Lut::parse_cube(&cube(2,8).replace("0.5 1","0.5 1 1")).is_err(), "4 values");
+        assert!(Lut::parse_cube("").is_err(), "empty file");
+    }
+    #[test]
+    fn vignette_darkens_the_corners() {
+        let image = flat(5,5,[1.,0.5,0.25,1.]);
+        let out = vignette(&image,0.3);
+        assert_eq!(out.get(2,2), image.get(2,2));
+        for (x,y) in [(0,0),(4,0),(0,4),(4,4)] {
+            let (before,after) = (image.get(x,y),out.get(x,y));
+            for c in 0..3 {
+                assert!(after[c] < before[c], "corner {x},{y} isn't darker");
+            }
+            assert_eq!(after[3], before[3]);
+        }
+    }
+    #[test]
+    fn sharpen_keeps_flat_images() {
+        let image = flat(4,3,[0.3,2.,0.,1.]);
+        assert_eq!(sharpen(&image,0.5).pixels, image.pixels);
+    }
+    #[test]
+    fn sharpen_raises_contrast() {
+        let mut image = flat(3,3,[0.5,0.5,0.5,1.]);
+        image.set(1,1,[1.,1.,1.,1.]);
+        let out = sharpen(&image,0.5);
+        assert!(out.get(1,1)[0] > 1.);
+        assert!(out.get(0,1)[0] < 0.5);
+    }
+    #[test]
+    fn bloom_below_threshold_keeps_the_image() {
+        let image = gradient(6,4,0.9);
+        assert_eq!(bloom(&image,1.,0.5,2).pixels, image.pixels);
+    }
+    #[test]
+    fn bloom_spreads_bright_pixels() {
+        let mut image = flat(8,8,[0.,0.,0.,1.]);
+        image.set(4,4,[10.,10.,10.,1.]);
+        let out = bloom(&image,1.,0.5,1);
+        assert!(out.get(2,4)[0] > 0.);
+        assert!(out.get(4,4)[0] > 10.);
+    }
+}
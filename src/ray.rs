@@ -1,6 +1,6 @@
 use my_math::prelude::*;
 use crate::chunk::SIZE;
-use crate::chunk::ChunkData;
+use crate::chunk::{ChunkData,BrickMap,Brick,BRICK_SIZE};
 use crate::octree::*;
 
 fn next_node(curr_quad: i32, txm: f32, tym: f32,tzm: f32) -> i32 {
@@ -44,7 +44,7 @@ fn first_node (tx0: f32, ty0: f32, tz0: f32,txm: f32, tym: f32,tzm:f32) -> i32 {
     return out;
 }
 
-#[derive(Debug,Copy,Clone)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
 pub enum Dir {
     X,
     NegX,
@@ -53,6 +53,20 @@ pub enum Dir {
     Z,
     NegZ,
 }
+impl Dir {
+    /// 0 => X, 1 => Y, 2 => Z
+    pub fn from_axis(axis: usize, positive: bool) -> Dir {
+        match (axis,positive) {
+            (0,true)  => Dir::X,
+            (0,false) => Dir::NegX,
+            (1,true)  => Dir::Y,
+            (1,false) => Dir::NegY,
+            (2,true)  => Dir::Z,
+            (2,false) => Dir::NegZ,
+            _ => panic!("invalid axis {axis}"),
+        }
+    }
+}
 impl std::ops::Neg for Dir {
     type Output = Self;
 
@@ -92,6 +106,17 @@ impl Into<IVec3> for Dir {
     }
 }
 
+/// Result of every CPU ray cast, mirrors `RayHit` in the shaders
+#[derive(Debug,Copy,Clone)]
+pub struct RayHit {
+    pub voxel_pos: IVec3,
+    /// Axis the ray crossed to enter the voxel, the normal of the hit face is `-dir`
+    pub dir: Dir,
+    /// Distance from the ray start, in voxels if the ray direction is normalized
+    pub dist: f32,
+    pub color: u32,
+}
+
 pub fn hit_direction(hit: Vec3, dir: Vec3) -> Dir {
     let x = (hit.x - hit.x.round()).abs();
     let y = (hit.y - hit.y.round()).abs();
@@ -118,12 +143,21 @@ pub fn hit_direction(hit: Vec3, dir: Vec3) -> Dir {
     }
 }
 
-pub fn ray_octree_dir<'a>(start: Vec3, dir: Vec3, chunk_data: &'a Octree) -> Option<(&'a OctreeNode,Dir)> {
-    if let Some((node,t)) = ray_octree(start,dir,chunk_data) {
-        let dir = hit_direction(start + dir * t,dir);
-        return Some((node,dir));
-    } else {
-        return None;
+/// Builds the hit for a (possibly bigger than one voxel) octree leaf hit at `t`
+fn octree_node_hit(start: Vec3, dir: Vec3, node: &OctreeNode, t: f32) -> RayHit {
+    // nudge the point inside of the node so it rounds down to the right voxel
+    let inside = start + dir * (t + 1e-3);
+    let max = node.position + (node.size as i32 - 1);
+    let voxel_pos = ivec3!(
+        (inside.x.floor() as i32).clamp(node.position.x,max.x),
+        (inside.y.floor() as i32).clamp(node.position.y,max.y),
+        (inside.z.floor() as i32).clamp(node.position.z,max.z)
+    );
+    RayHit {
+        voxel_pos,
+        dir: hit_direction(start + dir * t,dir),
+        dist: t,
+        color: node.material,
     }
 }
 //struct StackNode {
@@ -142,7 +176,10 @@ pub fn ray_octree_dir<'a>(start: Vec3, dir: Vec3, chunk_data: &'a Octree) -> Opt
 //}
 pub static mut MAX_STACK_DEPTH: usize = 0;
 
-pub fn ray_octree_stack<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Option<(&'a OctreeNode,f32)> {
+pub fn ray_octree_stack(start: Vec3, dir: Vec3, octree: &Octree) -> Option<RayHit> {
+    ray_octree_stack_node(start,dir,octree).map(|(node,t)| octree_node_hit(start,dir,node,t))
+}
+fn ray_octree_stack_node<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Option<(&'a OctreeNode,f32)> {
     let mut start = start;
 
     let root_node_idx = 0;
@@ -264,7 +301,10 @@ pub fn ray_octree_stack<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Optio
     }
     return None;
 }
-pub fn ray_octree_max<'a>(start: Vec3, dir: Vec3, octree: &'a Octree, max: f32) -> Option<(&'a OctreeNode,f32)> {
+pub fn ray_octree_max(start: Vec3, dir: Vec3, octree: &Octree, max: f32) -> Option<RayHit> {
+    ray_octree_max_node(start,dir,octree,max).map(|(node,t)| octree_node_hit(start,dir,node,t))
+}
+fn ray_octree_max_node<'a>(start: Vec3, dir: Vec3, octree: &'a Octree, max: f32) -> Option<(&'a OctreeNode,f32)> {
     let mut start = start;
 
     let root_node_idx = 0;
@@ -395,38 +435,95 @@ pub fn ray_octree_max<'a>(start: Vec3, dir: Vec3, octree: &'a Octree, max: f32)
     }
 }
 
-pub fn ray_octree<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Option<(&'a OctreeNode,f32)> {
-    let mut start = start;
+pub fn ray_octree(start: Vec3, dir: Vec3, octree: &Octree) -> Option<RayHit> {
+    ray_octree_node(start,dir,octree).map(|(node,t)| octree_node_hit(start,dir,node,t))
+}
+fn ray_octree_node<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Option<(&'a OctreeNode,f32)> {
+    let classify = |node_idx: i32| {
+        let node = &octree.nodes[node_idx as usize];
+        if node.has_children {
+            OctreeChild::Node(node_idx as usize)
+        } else if node.is_full {
+            OctreeChild::Leaf(node)
+        } else {
+            OctreeChild::Empty
+        }
+    };
+    let root = &octree.nodes[0];
+    traverse_octree(start,dir,root.position,root.size,classify(0),
+        &|node_idx,child| classify(octree.nodes[node_idx].children_idx[child]))
+}
 
-    let root_node_idx = 0;
-    let node = &octree.nodes[root_node_idx];
+/// Same traversal as `ray_octree` over a `PackedOctree`
+pub fn ray_packed_octree(start: Vec3, dir: Vec3, octree: &PackedOctree) -> Option<RayHit> {
+    let (material,t) = ray_packed_octree_material(start,dir,octree)?;
+    let hit = start + dir * t;
+    let inside = start + dir * (t + 1e-3);
+    Some(RayHit {
+        voxel_pos: ivec3!(inside.x.floor() as i32,inside.y.floor() as i32,inside.z.floor() as i32),
+        dir: hit_direction(hit,dir),
+        dist: t,
+        color: material,
+    })
+}
+fn ray_packed_octree_material(start: Vec3, dir: Vec3, octree: &PackedOctree) -> Option<(u32,f32)> {
+    // the root is always an inner node, an empty tree just has no valid children
+    traverse_octree(start,dir,octree.position,octree.size,OctreeChild::Node(0),&|node_idx,child| {
+        let node = &octree.nodes[node_idx];
+        let child_idx = node.child_ptr as usize + child;
+        if node.is_leaf(child) {
+            OctreeChild::Leaf(octree.nodes[child_idx].child_ptr)
+        } else if node.is_valid(child) {
+            OctreeChild::Node(child_idx)
+        } else {
+            OctreeChild::Empty
+        }
+    })
+}
 
-    let node_pos = node.position;
+/// A node as seen by `traverse_octree`
+enum OctreeChild<L> {
+    Empty,
+    Leaf(L),
+    /// Inner node, the index is passed back to the child lookup
+    Node(usize),
+}
+
+/// Parametric traversal of an octree in front to back order, shared by the octree layouts.
+/// `child(node_idx,i)` looks up the `i`th child of an inner node in `OctreeNode::children_idx`
+/// order, returns the first leaf the ray hits and the distance to it
+fn traverse_octree<L>(start: Vec3, dir: Vec3, pos: IVec3, size: u32, root: OctreeChild<L>,
+                      child: &dyn Fn(usize,usize) -> OctreeChild<L>) -> Option<(L,f32)> {
+    let mut start = start;
+    let size = size as f32;
 
     let mut mask:u8 = 0;
     if dir.x < 0. {
-        start.x = 2. * node_pos.x as f32 + node.size as f32 - start.x;
+        start.x = 2. * pos.x as f32 + size - start.x;
         mask |= 4;
     }
     if dir.y < 0. {
-        start.y = 2. * node_pos.y as f32 + node.size as f32 - start.y;
+        start.y = 2. * pos.y as f32 + size - start.y;
         mask |= 2;
     }
     if dir.z < 0. {
-        start.z = 2. * node_pos.z as f32 + node.size as f32 - start.z;
+        start.z = 2. * pos.z as f32 + size - start.z;
         mask |= 1;
     }
 
-    let tx0 = (node_pos.x as f32 - start.x) / dir.x.abs();
-    let ty0 = (node_pos.y as f32 - start.y) / dir.y.abs();
-    let tz0 = (node_pos.z as f32 - start.z) / dir.z.abs();
-
-    let tx1 = (node_pos.x as f32 + node.size as f32 - start.x) / dir.x.abs();
-    let ty1 = (node_pos.y as f32 + node.size as f32 - start.y) / dir.y.abs();
-    let tz1 = (node_pos.z as f32 + node.size as f32 - start.z) / dir.z.abs();
+    let t0 = (
+        (pos.x as f32 - start.x) / dir.x.abs(),
+        (pos.y as f32 - start.y) / dir.y.abs(),
+        (pos.z as f32 - start.z) / dir.z.abs(),
+    );
+    let t1 = (
+        (pos.x as f32 + size - start.x) / dir.x.abs(),
+        (pos.y as f32 + size - start.y) / dir.y.abs(),
+        (pos.z as f32 + size - start.z) / dir.z.abs(),
+    );
 
-    let t_min = tx0.max(ty0).max(tz0);
-    let t_max = tx1.min(ty1).min(tz1);
+    let t_min = t0.0.max(t0.1).max(t0.2);
+    let t_max = t1.0.min(t1.1).min(t1.2);
 
     let intersects: bool = t_min < t_max ;
 
@@ -434,29 +531,27 @@ pub fn ray_octree<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Option<(&'a
         return None;
     }
 
-    return proc_subtree(start,dir,mask,octree,root_node_idx as i32,tx0,ty0,tz0,tx1,ty1,tz1);
-
-    fn proc_subtree(start: Vec3, dir: Vec3, mask: u8,octree: &Octree, node_idx: i32,
-                    tx0:f32,ty0:f32,tz0:f32,tx1:f32,ty1:f32,tz1:f32) -> Option<(&OctreeNode,f32)> {
-        let t1_is_pos = tx1 >= 0. && ty1 >= 0. && tz1 >= 0. ;
+    return proc_subtree(mask,child,root,t0,t1);
 
-        if !t1_is_pos {
+    fn proc_subtree<L>(mask: u8, child: &dyn Fn(usize,usize) -> OctreeChild<L>, node: OctreeChild<L>,
+                       t0: (f32,f32,f32), t1: (f32,f32,f32)) -> Option<(L,f32)> {
+        let (tx0,ty0,tz0) = t0;
+        let (tx1,ty1,tz1) = t1;
+        if !(tx1 >= 0. && ty1 >= 0. && tz1 >= 0.) {
             return None;
         }
 
-        let node = &octree.nodes[node_idx as usize];
-        if !node.has_children {
-            if node.is_full && t1_is_pos {
-                if tx0 < 0. && ty0 < 0. && tz0 < 0.{
-                    return Some((node,0.));
-                }else {
-                    let t_min = tx0.max(ty0).max(tz0);
-                    return Some((node,t_min));
+        let node_idx = match node {
+            OctreeChild::Empty => return None,
+            OctreeChild::Leaf(leaf) => {
+                if tx0 < 0. && ty0 < 0. && tz0 < 0. {
+                    return Some((leaf,0.));
+                } else {
+                    return Some((leaf,tx0.max(ty0).max(tz0)));
                 }
-            } else {
-                return None;
             }
-        }
+            OctreeChild::Node(node_idx) => node_idx,
+        };
 
         let txm = (tx0 + tx1) /2.;
         let tym = (ty0 + ty1) /2.;
@@ -464,70 +559,31 @@ pub fn ray_octree<'a>(start: Vec3, dir: Vec3, octree: &'a Octree) -> Option<(&'a
 
         let mut curr_node = first_node(tx0,ty0,tz0,txm,tym,tzm);
 
-        let children_idx = node.children_idx;
-        //println!("hit index {curr_node}");
-
         while curr_node < 8 {
-            let child_idx = children_idx[curr_node as usize ^ mask as usize];
-            match curr_node {
-                0 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, tx0, ty0, tz0, txm, tym, tzm) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node(curr_node,txm,tym,tzm);
-                },
-                1 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, tx0,ty0,tzm,txm,tym,tz1) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node(curr_node,txm,tym,tz1);
-                },
-                2 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, tx0, tym, tz0, txm, ty1, tzm) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node(curr_node,txm,ty1,tzm);
-                },
-                3 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, tx0, tym, tzm, txm, ty1, tz1) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node (curr_node,txm, ty1, tz1,);
-                },
-                4 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, txm, ty0, tz0, tx1, tym, tzm) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node (curr_node,tx1,  tym,  tzm);
-                },
-                5 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, txm, ty0, tzm, tx1, tym, tz1) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node ( curr_node,tx1,  tym,  tz1);
-                },
-                6 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, txm, tym, tz0, tx1, ty1, tzm) {
-                        return Some((hit,t));
-                    }
-                    curr_node = next_node ( curr_node,tx1,  ty1,  tzm );
-                },
-                7 => {
-                    if let Some((hit,t)) = proc_subtree(start, dir, mask, octree,child_idx, txm, tym, tzm, tx1, ty1, tz1 ) {
-                        return Some((hit,t));
-                    }
-                    curr_node = 8;
-                },
+            let (t0,t1,next) = match curr_node {
+                0 => ((tx0,ty0,tz0),(txm,tym,tzm),next_node(curr_node,txm,tym,tzm)),
+                1 => ((tx0,ty0,tzm),(txm,tym,tz1),next_node(curr_node,txm,tym,tz1)),
+                2 => ((tx0,tym,tz0),(txm,ty1,tzm),next_node(curr_node,txm,ty1,tzm)),
+                3 => ((tx0,tym,tzm),(txm,ty1,tz1),next_node(curr_node,txm,ty1,tz1)),
+                4 => ((txm,ty0,tz0),(tx1,tym,tzm),next_node(curr_node,tx1,tym,tzm)),
+                5 => ((txm,ty0,tzm),(tx1,tym,tz1),next_node(curr_node,tx1,tym,tz1)),
+                6 => ((txm,tym,tz0),(tx1,ty1,tzm),next_node(curr_node,tx1,ty1,tzm)),
+                7 => ((txm,tym,tzm),(tx1,ty1,tz1),8),
                 _ => panic!(),
+            };
+            let hit = proc_subtree(mask,child,child(node_idx,curr_node as usize ^ mask as usize),t0,t1);
+            if hit.is_some() {
+                return hit;
             }
+            curr_node = next;
         }
-        return None;
+        None
     }
 }
 
 use std::f32;
 
-pub fn dda_3d_octree(start: Vec3, dir: Vec3, max_distance: f32,octree:&Octree) -> Option<(IVec3,Vec3)>{
+pub fn dda_3d_octree(start: Vec3, dir: Vec3, max_distance: f32,octree:&Octree) -> Option<RayHit>{
     let mut voxel = IVec3::new(
                             start.x.floor() as i32, 
                             start.y.floor() as i32, 
@@ -573,8 +629,9 @@ pub fn dda_3d_octree(start: Vec3, dir: Vec3, max_distance: f32,octree:&Octree) -
     let mut traveled_distance = 0.0;
     while traveled_distance < max_distance {
 
-        if octree.is_solid_at(voxel) {
-            return Some((voxel,start + dir * traveled_distance));
+        if let Some(material) = octree.material_at(voxel) {
+            let hit = start + dir * traveled_distance;
+            return Some(RayHit { voxel_pos: voxel, dir: hit_direction(hit,dir), dist: traveled_distance, color: material });
         }
 
         // Possibly faster using
@@ -606,7 +663,7 @@ pub fn dda_3d_octree(start: Vec3, dir: Vec3, max_distance: f32,octree:&Octree) -
     }
     None
 }
-pub fn dda_3d(start: Vec3, dir: Vec3, max_distance: f32,chunk_data:&Box<ChunkData>) -> Option<(IVec3,Vec3)>{
+pub fn dda_3d(start: Vec3, dir: Vec3, max_distance: f32,chunk_data:&Box<ChunkData>) -> Option<RayHit>{
     let mut voxel = IVec3::new(
                             start.x.floor() as i32, 
                             start.y.floor() as i32, 
@@ -655,8 +712,10 @@ pub fn dda_3d(start: Vec3, dir: Vec3, max_distance: f32,chunk_data:&Box<ChunkDat
         if !(voxel.x < 0 || voxel.x >= SIZE as i32 
             || voxel.y < 0 || voxel.y >= SIZE as i32 
             || voxel.z < 0 || voxel.z >= SIZE as i32) {
-            if chunk_data[voxel.x as usize][voxel.y as usize][voxel.z as usize] != 0 {
-                return Some((voxel,start + dir * traveled_distance));
+            let data = chunk_data[voxel.x as usize][voxel.y as usize][voxel.z as usize];
+            if data != 0 {
+                let hit = start + dir * traveled_distance;
+                return Some(RayHit { voxel_pos: voxel, dir: hit_direction(hit,dir), dist: traveled_distance, color: data as u32 });
             }
         }
 
@@ -689,3 +748,277 @@ pub fn dda_3d(start: Vec3, dir: Vec3, max_distance: f32,chunk_data:&Box<ChunkDat
     }
     None
 }
+
+/// CPU version of `dda_3d` from `dda_brick.comp`, `start` is in the local coordinates of the
+/// brickmap (its negative corner is at 0,0,0)
+pub fn dda_brickmap(start: Vec3, dir: Vec3, brickmap: &BrickMap) -> Option<RayHit> {
+    dda_brickmap_steps(start,dir,brickmap,f32::MAX).0
+}
+/// `dda_brickmap` that also counts the traversal steps like `RayHit::steps` in the shaders,
+/// and like them skips brickmaps that start more than `max_dist` away
+pub fn dda_brickmap_steps(start: Vec3, dir: Vec3, brickmap: &BrickMap, max_dist: f32) -> (Option<RayHit>,u32) {
+    let dir_arr = [dir.x,dir.y,dir.z];
+    let inv_dir = dir_arr.map(|x| 1. / x);
+    let step_dir = dir_arr.map(sign);
+    let grid_size = brickmap.grid.size;
+    let grid_size = [grid_size.x,grid_size.y,grid_size.z];
+
+    // Transform to brick coordinates
+    let mut ray_start = [start.x,start.y,start.z].map(|x| x / BRICK_SIZE as f32);
+
+    // AABB CUBE CLIP BEGIN
+    let mut t_enter = f32::MIN;
+    let mut t_exit  = f32::MAX;
+    for i in 0..3 {
+        let t0 = (0. - ray_start[i]) * inv_dir[i];
+        let t1 = (grid_size[i] as f32 - ray_start[i]) * inv_dir[i];
+        t_enter = t_enter.max(t0.min(t1));
+        t_exit  = t_exit.min(t0.max(t1));
+    }
+    if !(t_enter <= t_exit && t_exit >= 0.) {
+        return (None,0);
+    }
+    // Something closer was already drawn
+    if t_enter * BRICK_SIZE as f32 > max_dist {
+        return (None,0);
+    }
+    if t_enter > 0. {
+        for i in 0..3 {
+            ray_start[i] += dir_arr[i] * (t_enter - 0.001);
+        }
+    }
+    // AABB CUBE CLIP END
+
+    let start_cell = ray_start.map(|x| x.floor() as i32);
+    let mut grid_pos = start_cell;
+    let mut axis_dist = first_axis_dist(grid_pos,ray_start,step_dir,inv_dir);
+
+    let max_distance = t_exit - if t_enter >= 0. { t_enter } else { 0. };
+    let mut total_dist = 0.;
+    let mut steps = 0;
+    let mut axis = step_axis(axis_dist);
+    while total_dist < max_distance {
+        let brick_idx = brickmap.grid.get(ivec3!(grid_pos[0],grid_pos[1],grid_pos[2]));
+        if brick_idx != u32::MAX {
+            let mut uv3d = [0.;3];
+            for i in 0..3 {
+                uv3d[i] = if grid_pos == start_cell {
+                    // Handle edge case where camera origin is inside of block
+                    ray_start[i] - grid_pos[i] as f32
+                } else {
+                    ray_start[i] + dir_arr[i] * total_dist - grid_pos[i] as f32
+                } * BRICK_SIZE as f32;
+            }
+            let brick = &brickmap.data[brick_idx as usize];
+            if let Some((brick_pos,hit_axis)) = trace_brick(brick,uv3d,dir_arr,axis,&mut steps) {
+                let voxel = brick[brick_pos[0] as usize][brick_pos[1] as usize][brick_pos[2] as usize];
+                let voxel_pos = ivec3!(
+                    grid_pos[0] * BRICK_SIZE as i32 + brick_pos[0],
+                    grid_pos[1] * BRICK_SIZE as i32 + brick_pos[1],
+                    grid_pos[2] * BRICK_SIZE as i32 + brick_pos[2]
+                );
+                let hit = RayHit {
+                    voxel_pos,
+                    dir: Dir::from_axis(hit_axis,step_dir[hit_axis] > 0),
+                    dist: ray_voxel_entry(start,dir,voxel_pos),
+                    color: voxel.color,
+                };
+                return (Some(hit),steps);
+            }
+        }
+
+        axis = step_axis(axis_dist);
+        grid_pos[axis] += step_dir[axis];
+        total_dist = axis_dist[axis];
+        axis_dist[axis] += step_dir[axis] as f32 * inv_dir[axis];
+        steps += 1;
+    }
+    (None,steps)
+}
+/// Returns the position of the hit voxel in the brick and the axis of the last step, adds its steps to `steps`
+fn trace_brick(brick: &Brick, ray_start: [f32;3], dir: [f32;3], mut axis: usize, steps: &mut u32) -> Option<([i32;3],usize)> {
+    let ray_start = ray_start.map(|x| x.clamp(0.0001,7.9999));
+    let inv_dir = dir.map(|x| 1. / x);
+    let step_dir = dir.map(sign);
+    let mut brick_pos = ray_start.map(|x| x.floor() as i32);
+    let mut axis_dist = first_axis_dist(brick_pos,ray_start,step_dir,inv_dir);
+
+    while brick_pos.iter().all(|x| (0..BRICK_SIZE as i32).contains(x)) {
+        if brick[brick_pos[0] as usize][brick_pos[1] as usize][brick_pos[2] as usize].data != 0 {
+            return Some((brick_pos,axis));
+        }
+        axis = step_axis(axis_dist);
+        brick_pos[axis] += step_dir[axis];
+        axis_dist[axis] += step_dir[axis] as f32 * inv_dir[axis];
+        *steps += 1;
+    }
+    None
+}
+/// Same as `sign` in the shaders, unlike `f32::signum` it's 0 for 0
+fn sign(x: f32) -> i32 {
+    if x > 0. {
+        1
+    } else if x < 0. {
+        -1
+    } else {
+        0
+    }
+}
+/// Distance along the ray to the first boundary of `cell` on each axis
+fn first_axis_dist(cell: [i32;3], ray_start: [f32;3], step_dir: [i32;3], inv_dir: [f32;3]) -> [f32;3] {
+    let mut axis_dist = [0.;3];
+    for i in 0..3 {
+        axis_dist[i] = if step_dir[i] == 0 {
+            // the ray never crosses a boundary on this axis, the formula below could give -inf or NaN
+            f32::INFINITY
+        } else {
+            ((cell[i] as f32 - ray_start[i]) + 0.5 + step_dir[i] as f32 * 0.5) * inv_dir[i]
+        };
+    }
+    axis_dist
+}
+/// Same as `step_mask` in the shaders, returns the axis with the closest boundary
+fn step_axis(dist: [f32;3]) -> usize {
+    // From https://www.shadertoy.com/view/l33XWf
+    let pon = [dist[0] < dist[1], dist[1] < dist[2], dist[2] < dist[0]];
+    if pon[0] && !pon[2] {
+        0
+    } else if pon[1] && !pon[0] {
+        1
+    } else {
+        2
+    }
+}
+/// Distance along the ray at which it enters the voxel, 0 if it starts inside
+fn ray_voxel_entry(start: Vec3, dir: Vec3, voxel: IVec3) -> f32 {
+    let start = [start.x,start.y,start.z];
+    let dir = [dir.x,dir.y,dir.z];
+    let voxel = [voxel.x,voxel.y,voxel.z];
+    let mut t_enter: f32 = 0.;
+    for i in 0..3 {
+        // the ray stays between the two planes of this axis
+        if dir[i] == 0. {
+            continue;
+        }
+        let t0 = (voxel[i] as f32 - start[i]) / dir[i];
+        let t1 = (voxel[i] as f32 + 1. - start[i]) / dir[i];
+        t_enter = t_enter.max(t0.min(t1));
+    }
+    t_enter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small deterministic generator so the rays are the same on every run
+    struct Lcg(u32);
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+    }
+
+    fn test_octree() -> Octree {
+        let mut tree = Octree::new(32,ivec3!(0,0,0));
+        tree.union_shape(&Shape::Sphere { center: vec3!(16.,14.,17.), radius: 9. },1);
+        tree.union_shape(&Shape::Box { min: ivec3!(2,0,2), max: ivec3!(30,3,30) },2);
+        tree.subtract_shape(&Shape::Box { min: ivec3!(12,10,0), max: ivec3!(32,18,20) });
+        tree.add_block(ivec3!(5,20,6),3);
+        tree
+    }
+
+    #[test]
+    fn packed_octree_matches_octree() {
+        let tree = test_octree();
+        let packed = PackedOctree::from_octree(&tree);
+        let mut rng = Lcg(7);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // half of the rays start inside of the tree
+            let start = if rng.next() < 0.5 {
+                vec3!(rng.range(-20.,52.),rng.range(-20.,52.),rng.range(-20.,52.))
+            } else {
+                vec3!(rng.range(0.,32.),rng.range(0.,32.),rng.range(0.,32.))
+            };
+            let target = vec3!(rng.range(4.,28.),rng.range(0.,28.),rng.range(4.,28.));
+            let dir = (target - start).norm();
+
+            let expected = ray_octree(start,dir,&tree);
+            let packed_hit = ray_packed_octree(start,dir,&packed);
+            match (expected,packed_hit) {
+                (None,None) => (),
+                (Some(expected),Some(hit)) => {
+                    hits += 1;
+                    assert_eq!(hit.color, expected.color, "ray {:?} {:?}", start, dir);
+                    assert!((hit.dist - expected.dist).abs() < 1e-3, "ray {:?} {:?}", start, dir);
+                    assert_eq!(tree.material_at(hit.voxel_pos), Some(hit.color), "ray {:?} {:?}", start, dir);
+                }
+                (expected,hit) => panic!("ray {:?} {:?}: {:?} != {:?}", start, dir, hit, expected),
+            }
+        }
+        assert!(hits > 500, "only {hits} rays hit, the test scene is too sparse");
+    }
+    #[test]
+    fn packed_uniform_root() {
+        let full = PackedOctree::from_octree(&Octree::new_full(8,ivec3!(0,0,0),4));
+        assert_eq!(full.nodes.len(), 9);
+        assert_eq!(full.nodes[0].valid_mask, 0xFF);
+        assert_eq!(full.nodes[0].leaf_mask, 0xFF);
+        let hit = ray_packed_octree(vec3!(-2.,3.5,3.5),vec3!(1.,0.01,0.02).norm(),&full).unwrap();
+        assert_eq!(hit.color, 4);
+        assert_eq!(hit.voxel_pos, ivec3!(0,3,3));
+
+        let empty = PackedOctree::from_octree(&Octree::new(8,ivec3!(0,0,0)));
+        assert_eq!(empty.nodes.len(), 1);
+        assert!(ray_packed_octree(vec3!(-2.,3.5,3.5),vec3!(1.,0.01,0.02).norm(),&empty).is_none());
+    }
+    #[test]
+    fn packed_skips_free_nodes() {
+        let mut tree = test_octree();
+        tree.subtract_shape(&Shape::Sphere { center: vec3!(16.,14.,17.), radius: 12. });
+        let stats = tree.stats();
+        assert!(stats.orphaned_nodes > 0);
+
+        // every inner node becomes a group of 8, the root itself isn't part of any group
+        let packed = PackedOctree::from_octree(&tree);
+        assert_eq!(packed.nodes.len(), 1 + stats.mixed_nodes * 8);
+    }
+    #[test]
+    fn sign_of_zero() {
+        assert_eq!(sign(0.), 0);
+        assert_eq!(sign(-0.), 0);
+        assert_eq!(sign(2.5), 1);
+        assert_eq!(sign(-0.1), -1);
+    }
+    #[test]
+    fn brickmap_axis_aligned_rays() {
+        let mut brickmap = BrickMap::new(ivec3!(16,16,16));
+        brickmap.add_voxel(ivec3!(9,5,5),crate::chunk::Voxel { data: 1, color: 7 });
+
+        // start past the middle of the voxel on the axes the ray doesn't move along
+        for start in [vec3!(-3.,5.7,5.7),vec3!(-3.,5.2,5.9),vec3!(0.5,5.5,5.5)] {
+            let hit = dda_brickmap(start,vec3!(1.,0.,0.),&brickmap).unwrap();
+            assert_eq!(hit.voxel_pos, ivec3!(9,5,5));
+            assert_eq!(hit.color, 7);
+            assert_eq!(hit.dir, Dir::X);
+            assert!((hit.dist - (9. - start.x)).abs() < 1e-4);
+        }
+        let hit = dda_brickmap(vec3!(9.6,20.,5.6),vec3!(0.,-1.,0.),&brickmap).unwrap();
+        assert_eq!(hit.voxel_pos, ivec3!(9,5,5));
+        assert_eq!(hit.dir, Dir::NegY);
+        assert_eq!(hit.dist, 14.);
+
+        // starting on the plane of a voxel face
+        let hit = dda_brickmap(vec3!(9.,20.,5.),vec3!(0.,-1.,0.),&brickmap).unwrap();
+        assert_eq!(hit.voxel_pos, ivec3!(9,5,5));
+        assert_eq!(hit.dist, 14.);
+
+        assert!(dda_brickmap(vec3!(-3.,6.7,5.7),vec3!(1.,0.,0.),&brickmap).is_none());
+    }
+}
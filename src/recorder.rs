@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::{BufWriter,Write};
+use std::path::{Path,PathBuf};
+
+use crate::cpu_render::Image;
+use crate::screenshot;
+
+pub const RECORDING_DIR: &str = "./recordings";
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum RecordFormat {
+    /// Numbered PNG files
+    Png,
+    /// Numbered binary PPM files, the fastest to write
+    Ppm,
+    /// A single raw YUV 4:4:4 stream that ffmpeg and most players read directly
+    Y4m,
+}
+impl std::str::FromStr for RecordFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self,String> {
+        match s {
+            "png" => Ok(RecordFormat::Png),
+            "ppm" => Ok(RecordFormat::Ppm),
+            "y4m" => Ok(RecordFormat::Y4m),
+            _ => Err(format!("unknown format \"{s}\", expected png, ppm or y4m")),
+        }
+    }
+}
+
+/// Captures frames at a fixed simulated timestep. While recording the caller advances the
+/// simulation by `frame_time` instead of the real frame time, so the output plays back smoothly
+/// no matter how long each frame took to render
+pub struct Recorder {
+    pub format: RecordFormat,
+    /// Directory for the numbered files, or the path of the y4m file
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Simulated milliseconds between frames
+    pub frame_time: f32,
+    /// Only every Nth simulated frame is written
+    pub every_nth: u32,
+    /// Simulated frames so far
+    pub frame: u32,
+    /// Frames written so far
+    pub written: u32,
+    y4m: Option<BufWriter<File>>,
+}
+impl Recorder {
+    /// Starts a recording inside `dir`, named after the current time
+    pub fn start(format: RecordFormat, dir: &Path, width: u32, height: u32, fps: u32, every_nth: u32) -> std::io::Result<Self> {
+        let name = format!("recording_{}",screenshot::timestamp());
+        Self::with_path(format, &dir.join(name), width, height, fps, every_nth)
+    }
+    /// Like `start` but with an explicit output path. A `.y4m` extension is added for `Y4m`
+    pub fn with_path(format: RecordFormat, path: &Path, width: u32, height: u32, fps: u32, every_nth: u32) -> std::io::Result<Self> {
+        assert!(fps > 0 && every_nth > 0, "fps and every_nth have to be positive");
+        let (path,y4m) = match format {
+            RecordFormat::Y4m => {
+                let path = path.with_extension("y4m");
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut file = BufWriter::new(File::create(&path)?);
+                // The frame rate is a ratio, skipping frames divides it
+                writeln!(file,"YUV4MPEG2 W{width} H{height} F{fps}:{every_nth} Ip A1:1 C444")?;
+                (path,Some(file))
+            }
+            RecordFormat::Png | RecordFormat::Ppm => {
+                std::fs::create_dir_all(path)?;
+                (path.to_path_buf(),None)
+            }
+        };
+        Ok(Recorder {
+            format,
+            path,
+            width,
+            height,
+            frame_time: 1000. / fps as f32,
+            every_nth,
+            frame: 0,
+            written: 0,
+            y4m,
+        })
+    }
+    /// Call once per simulated frame, skipped frames aren't written
+    pub fn capture(&mut self, image: &Image) -> std::io::Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        if !frame.is_multiple_of(self.every_nth) {
+            return Ok(());
+        }
+        if image.width != self.width || image.height != self.height {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,format!(
+                "frame size changed during the recording from {}x{} to {}x{}",
+                self.width,self.height,image.width,image.height)));
+        }
+
+        match self.format {
+            RecordFormat::Png => {
+                let png = screenshot::encode_png(image.width,image.height,&screenshot::to_srgb8(image));
+                std::fs::write(self.frame_path("png"),png)?;
+            }
+            RecordFormat::Ppm => {
+                image.write_ppm(&self.frame_path("ppm"))?;
+            }
+            RecordFormat::Y4m => {
+                let file = self.y4m.as_mut().expect("y4m recorder without a file");
+                file.write_all(b"FRAME\n")?;
+                file.write_all(&to_yuv444(&screenshot::to_srgb8(image)))?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+    /// Whether the next `capture` writes a frame, lets the caller skip the texture read back
+    pub fn wants_frame(&self) -> bool {
+        self.frame.is_multiple_of(self.every_nth)
+    }
+    /// Skips a frame without reading it back
+    pub fn skip(&mut self) {
+        self.frame += 1;
+    }
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if let Some(file) = self.y4m.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+    fn frame_path(&self, extension: &str) -> PathBuf {
+        self.path.join(format!("frame_{:05}.{extension}",self.written))
+    }
+}
+
+/// sRGB to planar Y, U, V with the BT.601 studio range, which is what y4m players assume
+fn to_yuv444(rgb: &[u8]) -> Vec<u8> {
+    let pixels = rgb.len() / 3;
+    let mut out = vec![0; pixels * 3];
+    let (y_plane,rest) = out.split_at_mut(pixels);
+    let (u_plane,v_plane) = rest.split_at_mut(pixels);
+    for (i,pixel) in rgb.chunks_exact(3).enumerate() {
+        let (r,g,b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+        y_plane[i] = ( 16. + 0.257 * r + 0.504 * g + 0.098 * b).round().clamp(0.,255.) as u8;
+        u_plane[i] = (128. - 0.148 * r - 0.291 * g + 0.439 * b).round().clamp(0.,255.) as u8;
+        v_plane[i] = (128. + 0.439 * r - 0.368 * g - 0.071 * b).round().clamp(0.,255.) as u8;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_size_change_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("recorder_test_{}",std::process::id()));
+        let mut recorder = Recorder::with_path(RecordFormat::Ppm,&dir,4,2,60,2).unwrap();
+        recorder.capture(&Image::new(4,2)).unwrap();
+        // skipped frames aren't checked
+        recorder.capture(&Image::new(2,2)).unwrap();
+        let err = recorder.capture(&Image::new(2,2)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(recorder.written, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
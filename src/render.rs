@@ -0,0 +1,772 @@
+use my_math::prelude::*;
+
+use crate::camera::Camera;
+use crate::chunk::{self,Chunk};
+use crate::day_cycle::DayCycle;
+use crate::entity::{self,Entity};
+use crate::mesh::Mesh;
+use crate::post::{self,BloomMode,Lut,PostSettings};
+use crate::resolution::Upscale;
+use crate::shader::{compile_shader,ShaderProgram};
+use crate::utils::{self,Vao};
+use crate::vertex::UvVertex;
+
+/// Share of the sky light every surface gets, mirrored in `shade.comp`
+pub const AMBIENT: f32 = 0.1;
+/// Most light a face in shadow keeps, the same as a face perpendicular to the light
+pub const SHADOW_LIGHT: f32 = 0.5;
+/// How far shadow rays start off the surface, mirrored in `shadow.comp`
+pub const SHADOW_BIAS: f32 = 0.001;
+/// `fog_density` times the distance past `fog_start` at which the fog is 98% opaque
+pub const FOG_FULL: f32 = 2.;
+
+// The debug views, mirrored in `dda_brick.comp` and `draw_entity.comp`
+/// Traversal steps that show as full red in `DebugView::Steps`
+pub const HEATMAP_STEPS: f32 = 128.;
+/// Colors of the step heatmap, evenly spaced from 0 to `HEATMAP_STEPS`
+pub const HEATMAP: [Vec3;5] = [
+    Vec3 { x: 0., y: 0., z: 0. },
+    Vec3 { x: 0., y: 0., z: 1. },
+    Vec3 { x: 0., y: 1., z: 0. },
+    Vec3 { x: 1., y: 1., z: 0. },
+    Vec3 { x: 1., y: 0., z: 0. },
+];
+/// Distance that shows as half brightness in `DebugView::Depth`
+pub const DEPTH_VIEW_HALF: f32 = 64.;
+/// Width in pixels of the lines of `DebugView::Bricks` and `DebugView::Chunks`
+pub const DEBUG_LINE_PIXELS: f32 = 1.5;
+pub const BRICK_LINE: Vec3 = Vec3 { x: 1., y: 0.8, z: 0. };
+pub const CHUNK_LINE: Vec3 = Vec3 { x: 1., y: 0.1, z: 0.1 };
+
+/// Everything that changes how a frame looks, shared by the gpu passes and `cpu_render`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct RenderSettings {
+    /// Direction the light travels
+    pub light_dir: Vec3,
+    /// Color times intensity of the light
+    pub light_color: Vec3,
+    /// Direction the sunlight travels, drives the sky. Differs from `light_dir` at night
+    pub sun_dir: Vec3,
+    /// Trace a ray toward the light from every visible surface
+    pub shadows: bool,
+    /// Darken corners and crevices based on the neighbouring voxels
+    pub ambient_occlusion: bool,
+    /// 0 has no effect, 1 makes fully enclosed corners black
+    pub ao_strength: f32,
+    /// Accumulate path traced global illumination while the view stays the same
+    pub path_tracing: bool,
+    /// Bounces after the first hit, every one of them traces all brickmaps twice
+    pub path_bounces: u32,
+    /// Mixed into the random numbers of every path, the same seed gives the same samples
+    pub path_seed: u32,
+    /// Blend distant surfaces toward the sky
+    pub fog: bool,
+    /// Distance in voxels where the fog starts
+    pub fog_start: f32,
+    /// Per voxel past `fog_start`, see `fog_amount`
+    pub fog_density: f32,
+    /// Multiplies the linear color before the tone mapping
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    pub post: PostSettings,
+    /// Anything but `DebugView::Off` replaces the lit frame
+    pub debug_view: DebugView,
+}
+impl RenderSettings {
+    /// Strength the shading uses, 0 when ambient occlusion is off
+    pub fn ao_strength(&self) -> f32 {
+        if self.ambient_occlusion { self.ao_strength } else { 0. }
+    }
+    /// Density the sky pass uses, 0 when fog is off
+    pub fn fog_density(&self) -> f32 {
+        if self.fog { self.fog_density } else { 0. }
+    }
+    /// How much of the sky covers a surface `dist` voxels away, mirrored in `sky.comp`
+    pub fn fog_amount(&self, dist: f32) -> f32 {
+        let x = self.fog_density() * (dist - self.fog_start).max(0.);
+        1. - (-x * x).exp()
+    }
+    /// The settings the path traced samples depend on, changing any of the others keeps them
+    pub fn path_inputs(&self) -> PathInputs {
+        PathInputs {
+            light_dir:      self.light_dir,
+            light_color:    self.light_color,
+            sun_dir:        self.sun_dir,
+            fog_start:      self.fog_start,
+            fog_density:    self.fog_density(),
+            path_bounces:   self.path_bounces,
+            path_seed:      self.path_seed,
+        }
+    }
+    /// Fog that starts halfway out and hides the edge of the chunks loaded within `radius` chunks
+    pub fn fit_fog(&mut self, radius: f32) {
+        // Chunks are loaded by their centers, so the corners of the farthest ones can be missing
+        let end = (radius - std::f32::consts::FRAC_1_SQRT_2).max(1.) * chunk::SIZE as f32;
+        self.fog_start = end * 0.5;
+        self.fog_density = FOG_FULL / (end - self.fog_start);
+    }
+}
+impl Default for RenderSettings {
+    fn default() -> Self {
+        let mut settings = RenderSettings {
+            light_dir: Vec3::ZERO,
+            light_color: Vec3::ZERO,
+            sun_dir: Vec3::ZERO,
+            shadows: true,
+            ambient_occlusion: true,
+            ao_strength: 0.6,
+            path_tracing: false,
+            path_bounces: 2,
+            path_seed: 0,
+            fog: true,
+            fog_start: 0.,
+            fog_density: 0.,
+            exposure: 1.,
+            tone_map: ToneMap::Aces,
+            post: PostSettings::default(),
+            debug_view: DebugView::Off,
+        };
+        settings.fit_fog(crate::CHUNK_RADIUS);
+        DayCycle::default().apply(&mut settings);
+        settings
+    }
+}
+
+/// How `present.frag` maps the linear HDR color to the 0 to 1 range of the window
+#[repr(i32)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ToneMap {
+    /// Filmic curve with a soft shoulder and some extra contrast
+    Aces        = 0,
+    /// `c / (1 + c)`, flatter but never clips
+    Reinhard    = 1,
+}
+
+/// What the trace passes draw instead of the albedo. Every view but `Off` skips the lighting,
+/// the sky, the post passes and the tone mapping
+#[repr(i32)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum DebugView {
+    Off         = 0,
+    /// Traversal steps of all trace passes together, black through blue to red
+    Steps       = 1,
+    /// World space normals, `normal * 0.5 + 0.5`
+    Normals     = 2,
+    /// Voxel colors without any lighting
+    Albedo      = 3,
+    /// Voxel colors with lines along the edges of the bricks
+    Bricks      = 4,
+    /// Voxel colors with lines along the edges of the chunks and the entity
+    Chunks      = 5,
+    /// Brighter is closer, see `DEPTH_VIEW_HALF`
+    Depth       = 6,
+}
+impl DebugView {
+    /// The view after this one, `Off` comes after the last
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off      => DebugView::Steps,
+            DebugView::Steps    => DebugView::Normals,
+            DebugView::Normals  => DebugView::Albedo,
+            DebugView::Albedo   => DebugView::Bricks,
+            DebugView::Bricks   => DebugView::Chunks,
+            DebugView::Chunks   => DebugView::Depth,
+            DebugView::Depth    => DebugView::Off,
+        }
+    }
+}
+
+/// See `RenderSettings::path_inputs`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct PathInputs {
+    pub light_dir: Vec3,
+    pub light_color: Vec3,
+    pub sun_dir: Vec3,
+    pub fog_start: f32,
+    pub fog_density: f32,
+    pub path_bounces: u32,
+    pub path_seed: u32,
+}
+
+/// Mode uniform of `path_trace.comp` and `path_step.comp`
+#[repr(i32)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum PathMode {
+    // path_trace.comp, once per brickmap
+    ClosestHit  = 0,
+    Shadow      = 1,
+    // path_step.comp, once per frame or bounce
+    Start       = 2,
+    Hit         = 3,
+    Bounce      = 4,
+    Resolve     = 5,
+}
+
+/// PCG hash, the random number generator of the path tracer
+pub fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Cosine weighted direction around `normal`. The random numbers only depend on the seed,
+/// the sample, the pixel and the bounce, so the gpu and `cpu_render` pick the same directions
+pub fn cosine_hemisphere(normal: Vec3, seed: u32, sample: u32, pixel: (u32,u32), bounce: u32) -> Vec3 {
+    let state = pcg(pcg(pcg(pcg(seed ^ sample).wrapping_add(pixel.0)).wrapping_add(pixel.1)).wrapping_add(bounce));
+    let state2 = pcg(state);
+    let u1 = (state  >> 8) as f32 / 16777216.;
+    let u2 = (state2 >> 8) as f32 / 16777216.;
+
+    let r = u1.sqrt();
+    let phi = std::f32::consts::TAU * u2;
+    let axis = if normal.x.abs() > 0.5 { Vec3::Y } else { Vec3::X };
+    let tangent = axis.cross(normal).norm();
+    let bitangent = normal.cross(tangent);
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1. - u1).sqrt()).norm()
+}
+
+/// Column major world to clip space matrix that puts points where the trace passes of `width` by
+/// `height` targets see them, for rasterizing over the traced image. The far plane is at infinity
+/// and the clip w is the distance along the view direction
+pub fn view_projection(camera: &Camera, width: u32, height: u32) -> [f32;16] {
+    // Same basis as the ray generation in the trace shaders
+    let forward = camera.dir;
+    let left    = forward.cross(Vec3::Y).norm();
+    let up      = left.cross(forward);
+    let scale   = (camera.fov * 0.5).to_radians().tan();
+    let aspect  = width as f32 / height as f32;
+
+    // The rays go through the corners of the pixels but the pixels are drawn around their centers,
+    // which is half a pixel over
+    let rows = [
+        left * (-1. / (scale * aspect)) + forward * (1. / width as f32),
+        up * (1. / scale) + forward * (1. / height as f32),
+        forward,
+        forward,
+    ];
+    let offsets = [0., 0., -2. * camera.near, 0.];
+    let mut out = [0.;16];
+    for (i,(row,offset)) in rows.iter().zip(offsets).enumerate() {
+        out[i]      = row.x;
+        out[4 + i]  = row.y;
+        out[8 + i]  = row.z;
+        out[12 + i] = offset - row.dot(camera.pos);
+    }
+    out
+}
+
+/// Maps world positions into the local space of a brickmap: a rotation given by its columns and an offset.
+/// Chunks only translate, entities also rotate
+#[derive(Debug,Clone,Copy)]
+pub struct LocalTransform {
+    pub x: Vec3,
+    pub y: Vec3,
+    pub z: Vec3,
+    pub offset: Vec3,
+}
+impl LocalTransform {
+    pub fn chunk(pos: IVec3) -> Self {
+        LocalTransform {
+            x: Vec3::X,
+            y: Vec3::Y,
+            z: Vec3::Z,
+            offset: -1. * (pos * chunk::SIZE as i32).as_vec3(),
+        }
+    }
+    pub fn entity(entity: &Entity) -> Self {
+        let (offset,x) = entity::ray_to_local(entity,Vec3::ZERO,Vec3::X);
+        let (_,y) = entity::ray_to_local(entity,Vec3::ZERO,Vec3::Y);
+        let (_,z) = entity::ray_to_local(entity,Vec3::ZERO,Vec3::Z);
+        LocalTransform { x, y, z, offset }
+    }
+    pub fn point(&self, pos: Vec3) -> Vec3 {
+        self.dir(pos) + self.offset
+    }
+    pub fn dir(&self, dir: Vec3) -> Vec3 {
+        self.x * dir.x + self.y * dir.y + self.z * dir.z
+    }
+    /// Local direction back to world space, the rotation is orthonormal so this is its transpose
+    pub fn dir_to_world(&self, dir: Vec3) -> Vec3 {
+        vec3!(self.x.dot(dir), self.y.dot(dir), self.z.dot(dir))
+    }
+    pub unsafe fn set_uniforms(&self, program: ShaderProgram) {
+        program.set_vec3("to_local_x",self.x);
+        program.set_vec3("to_local_y",self.y);
+        program.set_vec3("to_local_z",self.z);
+        program.set_vec3("to_local_offset",self.offset);
+    }
+}
+
+/// The screen sized images the passes share, bound to the image units in the shaders
+pub struct RenderTargets {
+    pub width: u32,
+    pub height: u32,
+    /// Unit 0, albedo after the trace passes and the shaded color after `shade.comp`
+    pub color: u32,
+    /// Unit 1, distance to the closest hit
+    pub depth: u32,
+    /// Unit 2, world space normals
+    pub normals: u32,
+    /// Unit 3, 0 where the surface is in shadow
+    pub shadow: u32,
+    /// Unit 4, sum of the path traced samples and their count in w
+    pub accum: u32,
+    /// Units 5 to 7, the state of every path between passes, see `path_step.comp`
+    pub path_origin: u32,
+    pub path_dir: u32,
+    pub path_hit: u32,
+    /// Scratch for the post passes that read the neighbours of a pixel
+    pub post: u32,
+    /// Half resolution, see `post::bloom_size`
+    pub bloom: [u32;2],
+    /// Path traced samples in `accum`
+    pub samples: u32,
+    /// What `accum` was rendered with, the accumulation restarts when any of it changes
+    pub accum_view: Option<(Vec3,Vec3,f32,PathInputs)>,
+}
+impl RenderTargets {
+    pub fn new(width: u32, height: u32) -> Self {
+        let (bloom_width,bloom_height) = post::bloom_size(width,height);
+        // The post passes sample these with bilinear filtering
+        let linear = |texture: u32| unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            texture
+        };
+        RenderTargets {
+            width,
+            height,
+            color:      linear(utils::create_texture(width,height)),
+            depth:      utils::create_depth_texture(width,height),
+            normals:    utils::create_texture(width,height),
+            shadow:     utils::create_depth_texture(width,height),
+            accum:      utils::create_texture(width,height),
+            path_origin: utils::create_texture(width,height),
+            path_dir:   utils::create_texture(width,height),
+            path_hit:   utils::create_texture(width,height),
+            post:       utils::create_texture(width,height),
+            bloom:      [0;2].map(|_| linear(utils::create_texture(bloom_width,bloom_height))),
+            samples:    0,
+            accum_view: None,
+        }
+    }
+    /// Throws away the path traced samples, for changes the view doesn't cover like edits
+    pub fn reset_accumulation(&mut self) {
+        self.accum_view = None;
+    }
+    pub unsafe fn bind(&self) {
+        gl::BindImageTexture(0, self.color,   0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+        gl::BindImageTexture(1, self.depth,   0, gl::FALSE, 0, gl::READ_WRITE, gl::R32F);
+        gl::BindImageTexture(2, self.normals, 0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+        gl::BindImageTexture(3, self.shadow,  0, gl::FALSE, 0, gl::READ_WRITE, gl::R32F);
+        gl::BindImageTexture(4, self.accum,       0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+        gl::BindImageTexture(5, self.path_origin, 0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+        gl::BindImageTexture(6, self.path_dir,    0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+        gl::BindImageTexture(7, self.path_hit,    0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+    }
+    /// Replaces every image with one of the new size and binds them, the accumulation starts over
+    pub unsafe fn resize(&mut self, width: u32, height: u32) {
+        self.delete();
+        *self = RenderTargets::new(width,height);
+        self.bind();
+    }
+    pub unsafe fn delete(&self) {
+        let textures = [
+            self.color,self.depth,self.normals,self.shadow,self.accum,self.path_origin,self.path_dir,self.path_hit,
+            self.post,self.bloom[0],self.bloom[1],
+        ];
+        for texture in textures {
+            gl::DeleteTextures(1, &texture);
+        }
+    }
+    /// Work groups covering the images, the shaders use 16x16 groups
+    fn groups(&self) -> (u32,u32) {
+        groups(self.width,self.height)
+    }
+}
+
+fn groups(width: u32, height: u32) -> (u32,u32) {
+    (width /16 +1, height/16 +1)
+}
+
+/// The shader programs of a frame. `render` traces into the bound `RenderTargets`,
+/// `present` draws the color image to the window
+pub struct Renderer {
+    pub clear_program:          ShaderProgram,
+    pub dda_program:            ShaderProgram,
+    pub draw_entity_program:    ShaderProgram,
+    pub shadow_program:         ShaderProgram,
+    pub shade_program:          ShaderProgram,
+    pub path_trace_program:     ShaderProgram,
+    pub path_step_program:      ShaderProgram,
+    pub sky_program:            ShaderProgram,
+    pub present_program:        ShaderProgram,
+    pub bloom_program:          ShaderProgram,
+    pub grade_program:          ShaderProgram,
+    pub vignette_program:       ShaderProgram,
+    pub sharpen_program:        ShaderProgram,
+    pub screen_vao:             Vao,
+    /// 3D texture of the color grading `Lut`
+    pub lut_texture:            u32,
+    pub lut_size:               u32,
+    /// Filters for `present` when the render is smaller than the window
+    pub nearest_sampler:        u32,
+    pub linear_sampler:         u32,
+}
+impl Renderer {
+    pub unsafe fn new() -> Self {
+        let compute = |path: &str| {
+            let shader = compile_shader(gl::COMPUTE_SHADER,path);
+            let program = ShaderProgram::create_compute(shader);
+            gl::DeleteShader(shader);
+            program
+        };
+        let sampler = |filter: u32| {
+            let mut sampler = 0;
+            gl::GenSamplers(1, &mut sampler);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, filter as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, filter as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            sampler
+        };
+        let uv_passthrough_vert         = compile_shader(gl::VERTEX_SHADER,"./shaders/uv_passthrough.vert");
+        let present_frag                = compile_shader(gl::FRAGMENT_SHADER,"./shaders/present.frag");
+        let present_program             = ShaderProgram::create_program(uv_passthrough_vert,present_frag);
+        gl::DeleteShader(uv_passthrough_vert);
+        gl::DeleteShader(present_frag);
+
+        let mut screen_mesh = Mesh::new();
+        screen_mesh.verts = vec![
+            // Positions                            // Texture Coords
+            UvVertex { pos: Vec2::new(-1.,-1.), uv_pos: Vec2::new( 0.0, 0.0) }, // Top-left;
+            UvVertex { pos: Vec2::new(-1., 1.), uv_pos: Vec2::new( 0.0, 1.0) }, // Top-Right
+            UvVertex { pos: Vec2::new( 1., 1.), uv_pos: Vec2::new( 1.0, 1.0) }, // Bottom-Right
+            UvVertex { pos: Vec2::new( 1.,-1.), uv_pos: Vec2::new( 1.0, 0.0) }, // Bottom-Left
+        ];
+        screen_mesh.indices = vec![
+            0, 1, 2, // First triangle
+            2, 3, 0 // Second triangle
+        ];
+
+        let mut renderer = Renderer {
+            //dda_program:          compute("./shaders/dda_ray.comp"),
+            clear_program:          compute("./shaders/clear_texture.comp"),
+            dda_program:            compute("./shaders/dda_brick.comp"),
+            draw_entity_program:    compute("./shaders/draw_entity.comp"),
+            shadow_program:         compute("./shaders/shadow.comp"),
+            shade_program:          compute("./shaders/shade.comp"),
+            path_trace_program:     compute("./shaders/path_trace.comp"),
+            path_step_program:      compute("./shaders/path_step.comp"),
+            sky_program:            compute("./shaders/sky.comp"),
+            present_program,
+            bloom_program:          compute("./shaders/post_bloom.comp"),
+            grade_program:          compute("./shaders/post_grade.comp"),
+            vignette_program:       compute("./shaders/post_vignette.comp"),
+            sharpen_program:        compute("./shaders/post_sharpen.comp"),
+            screen_vao:             utils::vao_from_mesh(&screen_mesh),
+            lut_texture:            0,
+            lut_size:               0,
+            nearest_sampler:        sampler(gl::NEAREST),
+            linear_sampler:         sampler(gl::LINEAR),
+        };
+        renderer.set_lut(&Lut::default_grade());
+        renderer
+    }
+    /// Replaces the color grading lut
+    pub unsafe fn set_lut(&mut self, lut: &Lut) {
+        if self.lut_texture == 0 {
+            gl::GenTextures(1, &mut self.lut_texture);
+        }
+        gl::BindTexture(gl::TEXTURE_3D, self.lut_texture);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexImage3D(
+            gl::TEXTURE_3D,
+            0,
+            gl::RGB32F as i32,
+            lut.size as i32,
+            lut.size as i32,
+            lut.size as i32,
+            0,
+            gl::RGB,
+            gl::FLOAT,
+            lut.data.as_ptr() as *const _,
+        );
+        self.lut_size = lut.size;
+    }
+
+    /// Renders a frame into `targets`, which have to be bound.
+    /// Every trace pass depth tests against the depth image so the order only matters for speed:
+    /// passes stop early behind what is already drawn, which is why the chunks should be sorted front to back
+    pub unsafe fn render(
+        &self,
+        targets:    &mut RenderTargets,
+        camera:     &Camera,
+        settings:   &RenderSettings,
+        entity:     &Entity,
+        chunks:     &[&Chunk],
+        )
+    {
+        let (groups_x,groups_y) = targets.groups();
+        let barrier = || gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+
+        // Path traced samples only add up while nothing about the view changes
+        let view = (camera.pos,camera.dir,camera.fov,settings.path_inputs());
+        let clear_accum = settings.path_tracing && targets.accum_view != Some(view);
+        if clear_accum {
+            targets.samples = 0;
+        }
+        targets.accum_view = if settings.path_tracing { Some(view) } else { None };
+
+        let program = self.clear_program;
+        gl::UseProgram(*program);
+        program.set_int("clear_accum",clear_accum as i32);
+        gl::DispatchCompute(groups_x, groups_y, 1);
+        barrier();
+
+        // TRACE
+        let entity_transform = LocalTransform::entity(entity);
+        let (local_ray_pos,local_ray_dir) = entity::ray_to_local(entity,camera.pos,camera.dir);
+        let program = self.draw_entity_program;
+        gl::UseProgram(*program);
+        program.set_ivec3("ENTITY_SIZE",entity.size);
+        program.set_float("fov",camera.fov);
+        program.set_vec3("camera_pos",local_ray_pos);
+        program.set_vec3("camera_dir",local_ray_dir);
+        program.set_vec3("to_local_x",entity_transform.x);
+        program.set_vec3("to_local_y",entity_transform.y);
+        program.set_vec3("to_local_z",entity_transform.z);
+        program.set_int("ambient_occlusion",settings.ambient_occlusion as i32);
+        program.set_int("debug_view",settings.debug_view as i32);
+        bind_brickmap(entity.brickmap_grid_ssbo,entity.brickmap_data_ssbo);
+        gl::DispatchCompute(groups_x, groups_y, 1);
+        barrier();
+
+        let program = self.dda_program;
+        gl::UseProgram(*program);
+        program.set_float("fov",camera.fov);
+        program.set_int("CHUNK_SIZE",chunk::SIZE as i32);
+        program.set_vec3("camera_pos",camera.pos);
+        program.set_vec3("camera_dir",camera.dir);
+        program.set_int("ambient_occlusion",settings.ambient_occlusion as i32);
+        program.set_int("debug_view",settings.debug_view as i32);
+        for chunk in chunks {
+            program.set_ivec3("CHUNK_POS",chunk.pos);
+            bind_brickmap(chunk.brickmap_grid_ssbo,chunk.brickmap_data_ssbo);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+        }
+
+        // The debug views are done after the trace passes
+        if settings.debug_view != DebugView::Off {
+            return;
+        }
+
+        // Runs the current program once for every brickmap, for passes that trace in world space
+        let dispatch_brickmaps = |program: ShaderProgram| {
+            program.set_ivec3("GRID_SIZE",entity.brickmap.grid.size);
+            entity_transform.set_uniforms(program);
+            bind_brickmap(entity.brickmap_grid_ssbo,entity.brickmap_data_ssbo);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+
+            for chunk in chunks {
+                program.set_ivec3("GRID_SIZE",chunk.brickmap.grid.size);
+                LocalTransform::chunk(chunk.pos).set_uniforms(program);
+                bind_brickmap(chunk.brickmap_grid_ssbo,chunk.brickmap_data_ssbo);
+                gl::DispatchCompute(groups_x, groups_y, 1);
+                barrier();
+            }
+        };
+
+        // SHADOWS
+        // The path tracer always needs them for the first hit
+        if settings.shadows || settings.path_tracing {
+            let program = self.shadow_program;
+            gl::UseProgram(*program);
+            program.set_float("fov",camera.fov);
+            program.set_vec3("camera_pos",camera.pos);
+            program.set_vec3("camera_dir",camera.dir);
+            program.set_vec3("light_dir",settings.light_dir);
+            dispatch_brickmaps(program);
+        }
+
+        if settings.path_tracing {
+            // PATHS
+            let step = self.path_step_program;
+            gl::UseProgram(*step);
+            step.set_float("fov",camera.fov);
+            step.set_vec3("camera_pos",camera.pos);
+            step.set_vec3("camera_dir",camera.dir);
+            step.set_vec3("light_dir",settings.light_dir);
+            step.set_vec3("light_color",settings.light_color);
+            step.set_vec3("sun_dir",settings.sun_dir);
+            step.set_uint("seed",settings.path_seed);
+            step.set_uint("sample_index",targets.samples);
+            step.set_int("max_bounces",settings.path_bounces as i32);
+            step.set_int("mode",PathMode::Start as i32);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+
+            let trace = self.path_trace_program;
+            for bounce in 0..settings.path_bounces {
+                gl::UseProgram(*trace);
+                trace.set_int("mode",PathMode::ClosestHit as i32);
+                dispatch_brickmaps(trace);
+
+                gl::UseProgram(*step);
+                step.set_int("bounce",bounce as i32);
+                step.set_int("mode",PathMode::Hit as i32);
+                gl::DispatchCompute(groups_x, groups_y, 1);
+                barrier();
+
+                gl::UseProgram(*trace);
+                trace.set_vec3("light_dir",settings.light_dir);
+                trace.set_int("mode",PathMode::Shadow as i32);
+                dispatch_brickmaps(trace);
+
+                gl::UseProgram(*step);
+                step.set_int("mode",PathMode::Bounce as i32);
+                gl::DispatchCompute(groups_x, groups_y, 1);
+                barrier();
+            }
+
+            gl::UseProgram(*step);
+            step.set_int("mode",PathMode::Resolve as i32);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+            targets.samples += 1;
+        } else {
+            // SHADE
+            let program = self.shade_program;
+            gl::UseProgram(*program);
+            program.set_vec3("light_dir",settings.light_dir);
+            program.set_vec3("light_color",settings.light_color);
+            program.set_vec3("sun_dir",settings.sun_dir);
+            program.set_float("ao_strength",settings.ao_strength());
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+        }
+
+        // SKY AND FOG
+        let program = self.sky_program;
+        gl::UseProgram(*program);
+        program.set_float("fov",camera.fov);
+        program.set_vec3("camera_dir",camera.dir);
+        program.set_vec3("sun_dir",settings.sun_dir);
+        program.set_float("fog_start",settings.fog_start);
+        program.set_float("fog_density",settings.fog_density());
+        gl::DispatchCompute(groups_x, groups_y, 1);
+        barrier();
+
+        self.post_process(targets,&settings.post);
+    }
+    /// Same as `post::apply`. Passes that read neighbouring pixels sample through texture unit 0
+    /// and write to image unit 0, which points back at the color target afterwards
+    unsafe fn post_process(&self, targets: &RenderTargets, settings: &PostSettings) {
+        let (groups_x,groups_y) = targets.groups();
+        let barrier = || gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT | gl::TEXTURE_FETCH_BARRIER_BIT);
+        let bind_image = |texture: u32| gl::BindImageTexture(0, texture, 0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
+        let bind_source = |texture: u32| gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        if settings.bloom {
+            let (bloom_width,bloom_height) = post::bloom_size(targets.width,targets.height);
+            let (bloom_x,bloom_y) = groups(bloom_width,bloom_height);
+            let program = self.bloom_program;
+            gl::UseProgram(*program);
+            program.set_int("source",0);
+            program.set_float("threshold",settings.bloom_threshold);
+            program.set_float("intensity",settings.bloom_intensity);
+
+            program.set_int("mode",BloomMode::Bright as i32);
+            bind_source(targets.color);
+            bind_image(targets.bloom[0]);
+            gl::DispatchCompute(bloom_x, bloom_y, 1);
+            barrier();
+
+            program.set_int("mode",BloomMode::Blur as i32);
+            for _ in 0..settings.bloom_passes {
+                for axis in 0..2 {
+                    program.set_int("blur_axis",axis as i32);
+                    bind_source(targets.bloom[axis]);
+                    bind_image(targets.bloom[1 - axis]);
+                    gl::DispatchCompute(bloom_x, bloom_y, 1);
+                    barrier();
+                }
+            }
+
+            program.set_int("mode",BloomMode::Composite as i32);
+            bind_source(targets.bloom[0]);
+            bind_image(targets.color);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+        }
+        bind_image(targets.color);
+
+        if settings.grading {
+            let program = self.grade_program;
+            gl::UseProgram(*program);
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_3D, self.lut_texture);
+            gl::ActiveTexture(gl::TEXTURE0);
+            program.set_int("lut",1);
+            program.set_float("lut_size",self.lut_size as f32);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+        }
+
+        if settings.vignette {
+            let program = self.vignette_program;
+            gl::UseProgram(*program);
+            program.set_float("strength",settings.vignette_strength);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            barrier();
+        }
+
+        if settings.sharpen {
+            let program = self.sharpen_program;
+            gl::UseProgram(*program);
+            program.set_int("source",0);
+            program.set_float("strength",settings.sharpen_strength);
+            bind_source(targets.color);
+            bind_image(targets.post);
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            gl::MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT | gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+            let (w,h) = (targets.width as i32, targets.height as i32);
+            gl::CopyImageSubData(targets.post, gl::TEXTURE_2D, 0, 0, 0, 0, targets.color, gl::TEXTURE_2D, 0, 0, 0, 0, w, h, 1);
+            bind_image(targets.color);
+        }
+    }
+
+    /// Draws the color target to the window with the exposure and tone mapping of `settings`,
+    /// stretched over the viewport with the `upscale` filter
+    pub unsafe fn present(&self, targets: &RenderTargets, settings: &RenderSettings, upscale: Upscale) {
+        gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, targets.color);
+        let sampler = match upscale {
+            Upscale::Nearest => self.nearest_sampler,
+            Upscale::Bilinear => self.linear_sampler,
+        };
+        gl::BindSampler(0, sampler);
+        let program = self.present_program;
+        gl::UseProgram(*program);
+        program.set_float("exposure",settings.exposure);
+        program.set_int("tone_map",settings.tone_map as i32);
+        program.set_int("debug_view",(settings.debug_view != DebugView::Off) as i32);
+        self.screen_vao.draw_elements(gl::TRIANGLES);
+        // The post passes sample through unit 0 with the texture's own filter
+        gl::BindSampler(0, 0);
+    }
+}
+
+unsafe fn bind_brickmap(grid_ssbo: u32, data_ssbo: u32) {
+    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, grid_ssbo);
+    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 3, data_ssbo);
+}
@@ -0,0 +1,72 @@
+/// Scales are multiples of this, so tiny frame time changes don't reallocate the render targets
+pub const SCALE_STEP: f32 = 0.05;
+/// Most steps a single change moves, the frame time model is only rough
+const MAX_STEPS: f32 = 3.;
+
+/// How `Renderer::present` fills the window from a smaller render
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Upscale {
+    Nearest,
+    Bilinear,
+}
+
+/// Picks the resolution the trace passes render at, relative to the framebuffer,
+/// so that frames take about `target_ms`
+#[derive(Debug,Clone,Copy)]
+pub struct DynamicResolution {
+    /// Milliseconds a frame should take
+    pub target_ms: f32,
+    /// Render size over framebuffer size, on both axes
+    pub scale: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Renders at this scale instead of adapting
+    pub fixed: Option<f32>,
+    pub upscale: Upscale,
+    /// Frames to wait after a change, until the averaged frame time only covers the new scale
+    cooldown: u32,
+}
+impl DynamicResolution {
+    pub fn new(target_ms: f32) -> Self {
+        DynamicResolution {
+            target_ms,
+            scale: 1.,
+            min_scale: 0.3,
+            max_scale: 1.,
+            fixed: None,
+            upscale: Upscale::Bilinear,
+            cooldown: 0,
+        }
+    }
+    /// Call once per frame with the average frame time over the last `settle_frames` frames.
+    /// Returns whether the scale changed and the render targets need a new size
+    pub fn update(&mut self, average_ms: f32, settle_frames: u32) -> bool {
+        if let Some(fixed) = self.fixed {
+            let changed = self.scale != fixed;
+            self.scale = fixed;
+            return changed;
+        }
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return false;
+        }
+        if average_ms <= 0. {
+            return false;
+        }
+        // The trace passes cost about the same per pixel, so the frame time follows the area
+        let ideal = self.scale * (self.target_ms / average_ms).sqrt();
+        let steps = ((ideal - self.scale) / SCALE_STEP).round().clamp(-MAX_STEPS,MAX_STEPS);
+        let scale = (self.scale + steps * SCALE_STEP).clamp(self.min_scale,self.max_scale);
+        if (scale - self.scale).abs() < SCALE_STEP * 0.5 {
+            return false;
+        }
+        self.scale = scale;
+        self.cooldown = settle_frames;
+        true
+    }
+    /// Size of the render targets for a framebuffer
+    pub fn render_size(&self, width: u32, height: u32) -> (u32,u32) {
+        let scale = |size: u32| ((size as f32 * self.scale).round() as u32).max(1);
+        (scale(width), scale(height))
+    }
+}
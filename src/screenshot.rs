@@ -0,0 +1,228 @@
+use std::path::{Path,PathBuf};
+use std::time::{SystemTime,UNIX_EPOCH};
+
+use crate::cpu_render::Image;
+
+pub const SCREENSHOT_DIR: &str = "./captures";
+
+/// Reads back an RGBA32F texture like the one from `utils::create_texture`.
+/// The compute passes write it through image stores, so they have to be visible before the read
+pub unsafe fn read_texture(texture: u32, width: u32, height: u32) -> Image {
+    let mut image = Image::new(width,height);
+    gl::MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::GetTexImage(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA,
+        gl::FLOAT,
+        image.pixels.as_mut_ptr() as *mut _,
+    );
+    image
+}
+
+/// Writes the image to `dir` with a timestamped name and returns the path
+pub fn save_png(image: &Image, dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("screenshot_{}.png",timestamp()));
+    std::fs::write(&path,encode_png(image.width,image.height,&to_srgb8(image)))?;
+    Ok(path)
+}
+
+/// Seconds and milliseconds since the unix epoch, for file names that sort by time
+pub fn timestamp() -> String {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}_{:03}",time.as_secs(),time.subsec_millis())
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.,1.);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// 8 bit sRGB rows from the top of the screen down
+pub fn to_srgb8(image: &Image) -> Vec<u8> {
+    let mut out = Vec::with_capacity((image.width * image.height * 3) as usize);
+    for y in (0..image.height).rev() {
+        for x in 0..image.width {
+            let pixel = image.get(x,y);
+            out.extend(pixel[..3].iter().map(|c| (linear_to_srgb(*c) * 255.).round() as u8));
+        }
+    }
+    out
+}
+
+/// 8 bit RGB PNG. The image data goes into stored (uncompressed) deflate blocks,
+/// bigger files but no compression dependency
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    assert!(rgb.len() == (width * height * 3) as usize, "rgb data doesn't match the size");
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend(width.to_be_bytes());
+    ihdr.extend(height.to_be_bytes());
+    ihdr.extend([
+        8, // Bit depth
+        2, // Color type: RGB
+        0, // Compression
+        0, // Filter
+        0, // Interlace
+    ]);
+    write_chunk(&mut png,b"IHDR",&ihdr);
+
+    // Every scanline starts with its filter type, 0 = none
+    let row_len = width as usize * 3;
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+    for row in rgb.chunks_exact(row_len.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend(row);
+    }
+    write_chunk(&mut png,b"IDAT",&zlib_stored(&raw));
+    write_chunk(&mut png,b"IEND",&[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8;4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = u16::MAX as usize;
+    let mut out = vec![0x78, 0x01]; // Deflate, 32K window, no preset dictionary
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for chunk in data.chunks(5552) { // Largest run that can't overflow before the modulo
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a PNG into its chunks, checking the signature, lengths and CRCs on the way
+    fn png_chunks(png: &[u8]) -> Vec<([u8;4],Vec<u8>)> {
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8;4] = rest[4..8].try_into().unwrap();
+            let data = &rest[8..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&rest[4..8 + len]), "bad crc in {}", String::from_utf8_lossy(&kind));
+            chunks.push((kind,data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+    /// Joins the stored blocks of a zlib stream and checks its checksum
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        let mut out = Vec::new();
+        let mut rest = &zlib[2..];
+        loop {
+            let last = rest[0] & 1 != 0;
+            assert_eq!(rest[0] >> 1, 0, "not a stored block");
+            let len = u16::from_le_bytes([rest[1],rest[2]]);
+            let nlen = u16::from_le_bytes([rest[3],rest[4]]);
+            assert_eq!(len, !nlen);
+            out.extend(&rest[5..5 + len as usize]);
+            rest = &rest[5 + len as usize..];
+            if last {
+                break;
+            }
+        }
+        assert_eq!(rest, adler32(&out).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+    #[test]
+    fn adler32_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // long enough to need the modulo between runs
+        assert_eq!(adler32(&[0xFF;10000]), 0xB623EB2B);
+    }
+    #[test]
+    fn encode_2x2() {
+        let rgb = [
+            255,0,0,    0,255,0,
+            0,0,255,    255,255,255,
+        ];
+        let png = encode_png(2,2,&rgb);
+        let chunks = png_chunks(&png);
+        let kinds: Vec<&[u8;4]> = chunks.iter().map(|(kind,_)| kind).collect();
+        assert_eq!(kinds, [b"IHDR",b"IDAT",b"IEND"]);
+
+        assert_eq!(chunks[0].1, [0,0,0,2, 0,0,0,2, 8,2,0,0,0]);
+        assert_eq!(inflate_stored(&chunks[1].1), [
+            0, 255,0,0,   0,255,0,
+            0, 0,0,255,   255,255,255,
+        ]);
+        assert!(chunks[2].1.is_empty());
+    }
+    #[test]
+    fn zlib_stored_splits_blocks() {
+        let data: Vec<u8> = (0..150_000_u32).map(|i| (i % 251) as u8).collect();
+        assert_eq!(inflate_stored(&zlib_stored(&data)), data);
+        assert!(inflate_stored(&zlib_stored(&[])).is_empty());
+    }
+    #[test]
+    fn srgb_rows_start_at_the_top() {
+        let mut image = Image::new(1,2);
+        image.set(0,0,[0.,0.,0.,1.]);
+        image.set(0,1,[1.,0.5,0.,1.]);
+        assert_eq!(to_srgb8(&image), [255,188,0, 0,0,0]);
+    }
+}
@@ -3,7 +3,7 @@ use std::ptr;
 use std::str;
 use std::ffi::CString;
 
-use my_math::vec::{Vec3,IVec3};
+use my_math::vec::{Vec2,Vec3,IVec3};
 
 
 #[derive(Clone,Copy)]
@@ -44,6 +44,9 @@ impl ShaderProgram {
 
         ShaderProgram(program)
     }
+    pub unsafe fn set_vec2(self,name: &str,val: Vec2) {
+        gl::Uniform2f(GetUniformLocation(self.0,name), val.x, val.y);
+    }
     pub unsafe fn set_vec3(self,name: &str,val: Vec3) {
         gl::Uniform3f(GetUniformLocation(self.0,name), val.x, val.y, val.z);
     }
@@ -0,0 +1,25 @@
+#version 330 core
+
+in vec3 color;
+out vec4 out_color;
+
+// Distance to the closest hit from the trace passes, smaller than the window with dynamic resolution
+uniform sampler2D trace_depth;
+uniform vec2 viewport;
+uniform float fov;
+uniform float aspect;
+
+// Lines lying on a surface stay visible
+const float DEPTH_BIAS = 0.01;
+
+/// Lines only show in front of the traced surfaces
+void main() {
+    vec2 uv = gl_FragCoord.xy / viewport;
+    vec2 ndc = uv * 2. - 1.;
+    float scale = tan(radians(fov * 0.5));
+    // The clip w is the distance along the view direction, see `render::view_projection`
+    float dist = length(vec3(ndc.x * aspect * scale, ndc.y * scale, 1.)) / gl_FragCoord.w;
+    if (dist > texture(trace_depth, uv).r * (1. + DEPTH_BIAS))
+        discard;
+    out_color = vec4(color, 1.);
+}
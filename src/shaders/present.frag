@@ -0,0 +1,42 @@
+#version 430
+
+in vec2 TexCoords;
+out vec4 FragColor;
+
+// Linear HDR color from the compute passes
+uniform sampler2D screenTexture;
+// Scales the color before the tone mapping
+uniform float exposure;
+// Same as `render::ToneMap`
+uniform int tone_map;
+// The debug views show their colors as they are
+uniform bool debug_view;
+
+const int TONE_MAP_ACES = 0;
+const int TONE_MAP_REINHARD = 1;
+
+/// Same as `cpu_render::tone_map`
+vec3 aces(vec3 x) {
+    // Narkowicz's fit of the ACES filmic curve
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0., 1.);
+}
+
+vec3 linear_to_srgb(vec3 c) {
+    c = clamp(c, 0., 1.);
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1. / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+}
+
+/// Exposure, tone mapping and the sRGB encoding the window expects
+void main() {
+    vec3 color = texture(screenTexture, TexCoords).rgb;
+    if (debug_view) {
+        FragColor = vec4(linear_to_srgb(color), 1.);
+        return;
+    }
+    color *= exposure;
+    if (tone_map == TONE_MAP_ACES)
+        color = aces(color);
+    else
+        color = color / (1. + color);
+    FragColor = vec4(linear_to_srgb(color), 1.);
+}
@@ -1,10 +0,0 @@
-#version 430
-
-in vec2 TexCoords;
-out vec4 FragColor;
-
-uniform sampler2D screenTexture;
-
-void main() {
-    FragColor = texture(screenTexture, TexCoords);
-}
@@ -0,0 +1,61 @@
+use my_math::prelude::*;
+
+/// Sky straight up in full daylight
+pub const ZENITH: Vec3 = Vec3 { x: 0.22, y: 0.42, z: 0.85 };
+/// Sky at the horizon in full daylight, paler because the light went through more air
+pub const HORIZON: Vec3 = Vec3 { x: 0.65, y: 0.78, z: 0.95 };
+/// Sky with the sun far below the horizon
+pub const NIGHT: Vec3 = Vec3 { x: 0.01, y: 0.015, z: 0.04 };
+/// Glow toward the sun while it crosses the horizon
+pub const TWILIGHT: Vec3 = Vec3 { x: 1.0, y: 0.45, z: 0.15 };
+pub const SUN: Vec3 = Vec3 { x: 1.0, y: 0.95, z: 0.85 };
+/// The moon is always opposite to the sun
+pub const MOON: Vec3 = Vec3 { x: 0.8, y: 0.85, z: 0.95 };
+/// What rays below the horizon see in daylight
+pub const GROUND: Vec3 = Vec3 { x: 0.3, y: 0.28, z: 0.25 };
+/// Cosine of the angular radius of the sun and moon discs, about 1.4 degrees
+pub const SUN_DISC_COS: f32 = 0.9997;
+
+/// Color of the sky in `dir`, `sun_dir` is the direction the sunlight travels. The same function
+/// is in `sky.comp`, `shade.comp` and `path_step.comp`. The sun and moon discs are left out where
+/// their light is already counted as direct light
+pub fn sky_color(dir: Vec3, sun_dir: Vec3, discs: bool) -> Vec3 {
+    let to_sun = -1. * sun_dir;
+    let sun_cos = dir.dot(to_sun).max(0.);
+    // Full daylight once the sun is a bit above the horizon
+    let day = smoothstep(-0.2, 0.15, to_sun.y);
+    // Strongest with the sun right on the horizon
+    let twilight = (1. - to_sun.y.abs() * 4.).clamp(0.,1.);
+    let sun_visible = day.max(twilight);
+
+    // Rays close to the horizon go through more air, which scatters out the blue
+    let horizon = (1. - dir.y.max(0.)).powf(4.);
+    let mut color = mix(NIGHT, mix(ZENITH, HORIZON, horizon), day);
+    color = color + TWILIGHT * (twilight * horizon * (0.25 + 0.75 * sun_cos.powf(4.)));
+    // Forward scattering makes a halo around the sun
+    color = color + SUN * (sun_cos.powf(64.) * 0.4 * sun_visible);
+    if discs {
+        let moon_cos = dir.dot(sun_dir).max(0.);
+        color = color + SUN * (smoothstep(SUN_DISC_COS - 0.0002, SUN_DISC_COS, sun_cos) * sun_visible);
+        color = color + MOON * (smoothstep(SUN_DISC_COS - 0.0002, SUN_DISC_COS, moon_cos) * (1. - day));
+    }
+
+    // The ground fades in just below the horizon
+    let ground = mix(NIGHT, GROUND, day);
+    mix(color, ground, smoothstep(0., 0.05, -dir.y))
+}
+
+/// Sky light reaching a surface, the sky somewhat above its normal
+pub fn sky_ambient(normal: Vec3, sun_dir: Vec3) -> Vec3 {
+    sky_color((normal + vec3!(0.,1.5,0.)).norm(), sun_dir, false)
+}
+
+pub fn mix(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    a * (1. - t) + b * t
+}
+
+/// Same as GLSL `smoothstep`
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.,1.);
+    t * t * (3. - 2. * t)
+}
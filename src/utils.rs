@@ -53,6 +53,32 @@ pub fn create_texture(width: u32, height: u32) -> u32 {
     texture
 }
 
+/// Single channel R32F texture for the depth image the trace passes share
+pub fn create_depth_texture(width: u32, height: u32) -> u32 {
+    let mut texture: u32 = 0;
+    unsafe {
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::R32F as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RED,
+            gl::FLOAT,
+            null_mut(),
+        );
+    }
+    texture
+}
+
 
 #[derive(Clone,Copy)]
 pub struct Vao {
@@ -164,8 +190,10 @@ impl InputTracker {
 
         let (mouse_x, mouse_y) = window.get_cursor_pos();
 
-        let center_x = (crate::WIDTH / 2) as f64;
-        let center_y = (crate::HEIGHT / 2) as f64;
+        // The cursor is in window coordinates, which differ from the framebuffer on high dpi screens
+        let (width,height) = window.get_size();
+        let center_x = (width / 2) as f64;
+        let center_y = (height / 2) as f64;
 
         let delta_x = mouse_x   - center_x;
         let delta_y = (mouse_y  - center_y) * -1.;
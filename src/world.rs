@@ -0,0 +1,174 @@
+use my_math::prelude::*;
+use std::collections::HashMap;
+
+use crate::chunk::{self,Chunk,Voxel};
+use crate::ray::{self,RayHit};
+
+/// All the loaded chunks, every position taken or returned is in global voxel coordinates
+pub struct World {
+    pub chunks: HashMap<IVec3,Chunk>,
+}
+impl World {
+    pub fn new() -> Self {
+        World {
+            chunks: HashMap::new(),
+        }
+    }
+    pub fn insert_chunk(&mut self, chunk: Chunk) {
+        self.chunks.insert(chunk.pos,chunk);
+    }
+    /// Splits a global voxel position into the chunk position and the position inside the chunk
+    pub fn to_chunk_coords(pos: IVec3) -> (IVec3,IVec3) {
+        (pos.div_floor(chunk::SIZE as i32), pos.modulo(chunk::SIZE as i32))
+    }
+    /// Returns `None` for empty voxels and voxels in chunks that aren't loaded
+    pub fn get_voxel(&self, pos: IVec3) -> Option<Voxel> {
+        let (chunk_pos,local_pos) = Self::to_chunk_coords(pos);
+        let chunk = self.chunks.get(&chunk_pos)?;
+        chunk.brickmap.get_voxel(local_pos)
+    }
+    /// Setting a voxel with `data == 0` clears it. Returns false if the chunk isn't loaded or its
+    /// brickmap doesn't cover the position
+    pub fn set_voxel(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        let (chunk_pos,local_pos) = Self::to_chunk_coords(pos);
+        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+            return false;
+        };
+        if !chunk.brickmap.contains(local_pos) {
+            return false;
+        }
+        if voxel.data == 0 {
+            chunk.brickmap.remove_voxel(local_pos);
+        } else {
+            chunk.brickmap.add_voxel(local_pos,voxel);
+        }
+        chunk.dirty = true;
+        true
+    }
+    pub fn remove_voxel(&mut self, pos: IVec3) -> bool {
+        self.set_voxel(pos,Voxel { data: 0, color: 0 })
+    }
+    /// Uploads the edits of every chunk changed since the last call
+    pub unsafe fn update_dirty_chunks(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            if chunk.dirty {
+                chunk.update_ssbos();
+            }
+        }
+    }
+    /// Casts the ray against every loaded chunk and returns the closest hit
+    pub fn raycast(&self, start: Vec3, dir: Vec3, max_distance: f32) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        for chunk in self.chunks.values() {
+            let chunk_origin = chunk.pos * chunk::SIZE as i32;
+            let Some(mut hit) = ray::dda_brickmap(start - chunk_origin.as_vec3(),dir,&chunk.brickmap) else {
+                continue;
+            };
+            if hit.dist > max_distance || closest.is_some_and(|c| c.dist <= hit.dist) {
+                continue;
+            }
+            hit.voxel_pos = hit.voxel_pos + chunk_origin;
+            closest = Some(hit);
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = chunk::SIZE as i32;
+
+    /// Two small chunks next to the origin, the brickmaps only cover the first 16 voxels of
+    /// each chunk so the tests stay fast
+    fn test_world() -> World {
+        let mut world = World::new();
+        for pos in [ivec3!(0,0,0),ivec3!(-1,0,0)] {
+            world.insert_chunk(Chunk {
+                brickmap: chunk::BrickMap::new(ivec3!(16,16,16)),
+                brickmap_grid_ssbo: 0,
+                brickmap_data_ssbo: 0,
+                pos,
+                dirty: false,
+            });
+        }
+        world
+    }
+    fn voxel(color: u32) -> Voxel {
+        Voxel { data: 1, color }
+    }
+
+    #[test]
+    fn chunk_coords() {
+        assert_eq!(World::to_chunk_coords(ivec3!(3,4,5)), (ivec3!(0,0,0),ivec3!(3,4,5)));
+        assert_eq!(World::to_chunk_coords(ivec3!(-1,0,SIZE)), (ivec3!(-1,0,1),ivec3!(SIZE - 1,0,0)));
+    }
+    #[test]
+    fn set_get_remove() {
+        let mut world = test_world();
+        let positions = [ivec3!(3,4,5),ivec3!(-SIZE + 2,7,1)];
+
+        for (i,pos) in positions.iter().enumerate() {
+            assert!(world.get_voxel(*pos).is_none());
+            assert!(world.set_voxel(*pos,voxel(i as u32 + 10)));
+            assert_eq!(world.get_voxel(*pos).map(|v| v.color), Some(i as u32 + 10));
+        }
+        assert!(world.chunks.values().all(|chunk| chunk.dirty));
+
+        assert!(world.remove_voxel(positions[0]));
+        assert!(world.get_voxel(positions[0]).is_none());
+        assert_eq!(world.get_voxel(positions[1]).map(|v| v.color), Some(11));
+    }
+    #[test]
+    fn unloaded_chunk() {
+        let mut world = test_world();
+        let pos = ivec3!(0,SIZE,0);
+        assert!(!world.set_voxel(pos,voxel(1)));
+        assert!(!world.remove_voxel(pos));
+        assert!(world.get_voxel(pos).is_none());
+        assert!(world.chunks.values().all(|chunk| !chunk.dirty));
+    }
+    #[test]
+    fn outside_of_brickmap() {
+        let mut world = test_world();
+        for pos in [ivec3!(3,20,0),ivec3!(3,0,20),ivec3!(20,3,3)] {
+            assert!(!world.set_voxel(pos,voxel(1)));
+            assert!(world.get_voxel(pos).is_none());
+        }
+        // an out of range y or z would land in another cell of the grid
+        assert!(world.chunks.values().all(|chunk| !chunk.dirty && chunk.brickmap.data.is_empty()));
+    }
+    #[test]
+    fn raycast_hits() {
+        let mut world = test_world();
+        world.set_voxel(ivec3!(5,5,5),voxel(1));
+        world.set_voxel(ivec3!(-SIZE + 2,5,5),voxel(2));
+        let dir = vec3!(1.,0.001,0.002).norm();
+
+        let hit = world.raycast(vec3!(-10.,5.5,5.5),dir,100.).unwrap();
+        assert_eq!(hit.voxel_pos, ivec3!(5,5,5));
+        assert_eq!(hit.color, 1);
+        assert_eq!(hit.dir, ray::Dir::X);
+        assert!((hit.dist - 15.).abs() < 0.01);
+
+        // the voxel in the other chunk is in the way
+        let hit = world.raycast(vec3!(-SIZE as f32,5.5,5.5),dir,1000.).unwrap();
+        assert_eq!(hit.voxel_pos, ivec3!(-SIZE + 2,5,5));
+        assert_eq!(hit.color, 2);
+
+        let hit = world.raycast(vec3!(-SIZE as f32 + 10.,5.5,5.5),-dir,1000.).unwrap();
+        assert_eq!(hit.voxel_pos, ivec3!(-SIZE + 2,5,5));
+        assert_eq!(hit.dir, ray::Dir::NegX);
+    }
+    #[test]
+    fn raycast_misses() {
+        let mut world = test_world();
+        world.set_voxel(ivec3!(5,5,5),voxel(1));
+        let dir = vec3!(1.,0.001,0.002).norm();
+
+        assert!(world.raycast(vec3!(-10.,5.5,5.5),dir,10.).is_none());
+        assert!(world.raycast(vec3!(-10.,8.5,5.5),dir,100.).is_none());
+        assert!(world.raycast(vec3!(10.,5.5,5.5),dir,100.).is_none());
+    }
+}